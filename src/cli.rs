@@ -1,21 +1,86 @@
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::{Parser, ValueEnum};
+use clap::Subcommand;
 
-use crate::{error::*, path::RootPath, typ::CompileMode, watch, zine::ZineFile};
+use rayon::prelude::*;
 
-#[derive(Debug, Parser)]
-struct Cli {
-    action: Action,
-    #[clap(short, long, default_value = "pdf")]
-    mode: CompileMode,
-    file: Utf8PathBuf,
-}
+use crate::{
+    diag::{error_diagnostics, print_diagnostics},
+    error::*,
+    gallery::GalleryFormat,
+    path::{BaseDir, RootPath},
+    theme::Theme,
+    typ::{CompileMode, CompileOptions, DiagnosticsFormat},
+    watch,
+    zine::ZineFile,
+};
 
-#[derive(Clone, Debug, ValueEnum)]
+#[derive(Debug, Subcommand)]
 pub enum Action {
-    Compile,
+    /// Compile every zine in the basedir
+    Build {
+        #[clap(flatten)]
+        options: CompileOptions,
+        /// Any path inside the basedir
+        #[clap(default_value = ".")]
+        path: Utf8PathBuf,
+    },
+    /// Compile a zine
+    Compile {
+        #[clap(flatten)]
+        options: CompileOptions,
+        /// Zine to compile
+        file: Utf8PathBuf,
+    },
+    /// Compile a zine every time it or its themes change
     #[cfg(feature = "watch")]
-    Watch,
+    Watch {
+        #[clap(flatten)]
+        options: CompileOptions,
+        /// Zine to watch
+        file: Utf8PathBuf,
+    },
+    /// Render a sample zine with every theme, as a contact sheet of first pages
+    Gallery {
+        #[clap(short, long, default_value = "png")]
+        format: GalleryFormat,
+        /// Output file, relative to the basedir (the extension is added)
+        #[clap(short, long, default_value = "gallery")]
+        output: Utf8PathBuf,
+        /// Any path inside the basedir
+        #[clap(default_value = ".")]
+        path: Utf8PathBuf,
+    },
+    /// Create a basedir with a starter theme
+    Init {
+        #[clap(default_value = ".")]
+        path: Utf8PathBuf,
+    },
+    /// Create a markdown zine in content/<slug>/<slug>.md, with a frontmatter for its theme
+    New {
+        slug: String,
+        /// Theme of the zine (required when the basedir has several themes)
+        #[clap(short, long)]
+        theme: Option<String>,
+        /// Any path inside the basedir
+        #[clap(short, long, default_value = ".")]
+        basedir: Utf8PathBuf,
+    },
+    /// Inspect the themes in the basedir
+    Themes {
+        #[clap(subcommand)]
+        action: ThemesAction,
+        /// Any path inside the basedir
+        #[clap(short, long, default_value = ".", global = true)]
+        basedir: Utf8PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ThemesAction {
+    /// List all themes, flagging the broken ones
+    List,
+    /// Show the manifest and params of a theme
+    Show { name: String },
 }
 
 #[derive(Clone, Debug)]
@@ -25,38 +90,53 @@ pub enum SourceType {
 }
 
 impl SourceType {
-    pub fn from_ext(path: &Utf8Path) -> Self {
-        match path.extension().unwrap() {
-            "md" => Self::Markdown,
-            "typ" => Self::Typst,
-            _ => {
-                panic!("Use me with .typ/.md file!");
-            }
+    pub fn from_ext(path: &Utf8Path) -> Result<Self, Error> {
+        match path.extension() {
+            Some("md") => Ok(Self::Markdown),
+            Some("typ") => Ok(Self::Typst),
+            _ => UnsupportedFileSnafu { path }.fail(),
         }
     }
 
     // We take a RootPath and not a simple path because we need the BaseDir context
     // to resolve themes etc...
-    pub fn compile(&self, path: &RootPath, mode: CompileMode) -> Result<(), Error> {
-        trace!("SourceType::compile({path:?}, {mode:?})");
+    pub fn compile(&self, path: &RootPath, options: &CompileOptions) -> Result<(), Error> {
+        trace!("SourceType::compile({path:?}, {options:?})");
 
-        if path.path.is_dir() {
-            panic!("Can only compile a .md or .typ file, not folder!");
+        let res = self.compile_outputs(path, options);
+        // Typst errors are already reported, others are only printed for humans by the caller
+        if let (Err(e), DiagnosticsFormat::Json) = (&res, options.diagnostics) {
+            print_diagnostics(&error_diagnostics(&path.root, e), options.diagnostics);
         }
+        res
+    }
+
+    fn compile_outputs(&self, path: &RootPath, options: &CompileOptions) -> Result<(), Error> {
+        ensure!(
+            !path.absolute().is_dir(),
+            UnsupportedFileSnafu {
+                path: path.absolute()
+            }
+        );
 
-        let zine = ZineFile::new(path);
+        let zine = ZineFile::new(path)?;
 
-        let compiled_zine = match self {
-            Self::Markdown => zine.compile_md()?,
-            Self::Typst => zine.compile()?,
+        let compiled_zines = match self {
+            Self::Markdown => zine.compile_md(options)?,
+            Self::Typst => vec![zine.compile(options.diagnostics)?],
         };
 
-        match mode {
-            CompileMode::Png => {
-                compiled_zine.to_png()?;
-            }
-            CompileMode::Pdf => {
-                compiled_zine.to_pdf()?;
+        for compiled_zine in compiled_zines {
+            match options.mode {
+                CompileMode::Png => {
+                    compiled_zine.to_png(options.out_dir.as_deref())?;
+                }
+                CompileMode::Pdf => {
+                    compiled_zine.to_pdf(options.out_dir.as_deref())?;
+                }
+                CompileMode::Booklet => {
+                    compiled_zine.to_booklet(options.out_dir.as_deref(), options.duplex)?;
+                }
             }
         }
 
@@ -64,9 +144,155 @@ impl SourceType {
     }
 
     #[cfg(feature = "watch")]
-    pub fn watch(&self, path: &RootPath) -> Result<(), Error> {
-        watch::watch(self, path);
+    pub fn watch(&self, path: &RootPath, options: &CompileOptions) -> Result<(), Error> {
+        watch::watch(self, path, options);
 
         Ok(())
     }
 }
+
+/// Compile all zines found in the [`BaseDir`] in parallel.
+///
+/// Every zine is reported as it finishes, and an error is returned if any of them failed.
+pub fn build(basedir: &BaseDir, options: &CompileOptions) -> Result<(), Error> {
+    let zines = basedir.zines()?;
+    info!("Building {} zines in {basedir}", zines.len());
+
+    let failed = zines
+        .par_iter()
+        .filter(|zine| {
+            let res = SourceType::from_ext(&zine.path).and_then(|s| s.compile(zine, options));
+            match &res {
+                Ok(()) => info!("OK     {}", zine.path),
+                Err(e) => error!("FAILED {}: {e}", zine.path),
+            }
+            res.is_err()
+        })
+        .count();
+
+    info!(
+        "Built {} zines: {} succeeded, {} failed",
+        zines.len(),
+        zines.len() - failed,
+        failed
+    );
+
+    ensure!(
+        failed == 0,
+        BuildSnafu {
+            failed,
+            total: zines.len()
+        }
+    );
+
+    Ok(())
+}
+
+/// Run a [`ThemesAction`] on the themes of the [`BaseDir`].
+pub fn themes(basedir: &BaseDir, action: &ThemesAction) -> Result<(), Error> {
+    match action {
+        ThemesAction::List => themes_list(basedir),
+        ThemesAction::Show { name } => themes_show(basedir, name),
+    }
+}
+
+fn themes_list(basedir: &BaseDir) -> Result<(), Error> {
+    let names = basedir.themes()?;
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or_default();
+
+    for name in names {
+        let theme = match Theme::load(basedir, &name) {
+            Ok(theme) => theme,
+            Err(e) => {
+                println!("{name:width$}  BROKEN: {e}");
+                continue;
+            }
+        };
+
+        let problems = theme.problems();
+        if !problems.is_empty() {
+            println!("{name:width$}  BROKEN: {}", problems.join("; "));
+            continue;
+        }
+
+        let manifest = theme.manifest.as_ref();
+        println!(
+            "{name:width$}  {:8}  {}",
+            manifest.and_then(|m| m.version.as_deref()).unwrap_or("-"),
+            manifest
+                .and_then(|m| m.description.as_deref())
+                .unwrap_or("(no manifest)"),
+        );
+    }
+
+    Ok(())
+}
+
+fn themes_show(basedir: &BaseDir, name: &str) -> Result<(), Error> {
+    ensure!(
+        basedir.themes()?.iter().any(|theme| theme == name),
+        UnknownThemeSnafu {
+            name: name.to_string(),
+        }
+    );
+    let theme = Theme::load(basedir, name)?;
+
+    println!("Theme:       {}", theme.name);
+    // The theme file may come from a parent
+    let themefile = theme
+        .resolve("theme.typ")
+        .unwrap_or(theme.themefile.clone());
+    println!("Theme file:  {}", themefile.path);
+    if theme.parent.is_some() {
+        let parents: Vec<&str> = theme.chain()[1..].iter().map(|t| t.name.as_str()).collect();
+        println!("Parents:     {}", parents.join(" -> "));
+    }
+
+    match &theme.manifest {
+        None => println!("No theme.toml manifest, settings are not checked"),
+        Some(manifest) => {
+            if let Some(version) = &manifest.version {
+                println!("Version:     {version}");
+            }
+            if let Some(description) = &manifest.description {
+                println!("Description: {description}");
+            }
+            for font in theme.fonts() {
+                println!("Font:        {font}");
+            }
+
+            println!("Params:");
+            if manifest.params.is_empty() {
+                println!("  (none)");
+            }
+            for (key, param) in &manifest.params {
+                let mut line = format!("  {key}: {}", param.kind);
+                if param.required {
+                    line.push_str(" (required)");
+                }
+                if let Some(default) = &param.default {
+                    line.push_str(&format!(" = {default}"));
+                }
+                println!("{line}");
+                if let Some(description) = &param.description {
+                    println!("      {description}");
+                }
+            }
+        }
+    }
+
+    let problems = theme.problems();
+    if problems.is_empty() {
+        println!("Status:      OK");
+    } else {
+        for problem in problems {
+            println!("BROKEN:      {problem}");
+        }
+    }
+
+    Ok(())
+}
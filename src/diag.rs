@@ -0,0 +1,372 @@
+use camino::Utf8PathBuf;
+use serde::Serialize;
+use typst::{
+    diag::{Severity as TypstSeverity, SourceDiagnostic},
+    syntax::Span,
+    World,
+};
+
+use std::fmt;
+
+use crate::{
+    error::Error,
+    path::BaseDir,
+    srcmap::{frontmatter_location, MarkdownSource},
+    typ::DiagnosticsFormat,
+};
+
+/// A Typst error or warning, located in the file the author wrote.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where the diagnostic comes from, if known
+    #[serde(flatten)]
+    pub location: Option<Location>,
+    pub message: String,
+    /// How the error was reached, like function calls, from the innermost
+    pub trace: Vec<String>,
+    pub hints: Vec<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A location in a file of the basedir.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Location {
+    /// Path relative to the basedir
+    pub file: Utf8PathBuf,
+    /// Lines and columns in the file, unknown for a frontmatter key which was not found or for
+    /// Typst generated from a zine
+    pub range: Option<Range>,
+    /// The frontmatter key, for a diagnostic about a frontmatter setting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frontmatter_key: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A 1-indexed line and column.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// An empty range at this position.
+    pub fn to_range(self) -> Range {
+        Range {
+            start: self,
+            end: self,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    /// Format as `path:line:column`, followed by the frontmatter key if any.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(range) = &self.range {
+            write!(f, ":{}:{}", range.start.line, range.start.column)?;
+        }
+        if let Some(key) = &self.frontmatter_key {
+            write!(f, " (frontmatter {key})")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+
+        for point in &self.trace {
+            write!(f, "\n  {point}")?;
+        }
+
+        for hint in &self.hints {
+            write!(f, "\n  hint: {hint}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert Typst errors and warnings, pointing to the file/line/column they come from.
+///
+/// When the main source was generated from `markdown`, locations in it are reported against the
+/// markdown file (or the frontmatter key) instead.
+pub fn diagnostics(
+    world: &dyn World,
+    markdown: Option<&MarkdownSource>,
+    errors: &[SourceDiagnostic],
+    warnings: &[SourceDiagnostic],
+) -> Vec<Diagnostic> {
+    warnings
+        .iter()
+        .chain(errors)
+        .map(|diagnostic| Diagnostic {
+            severity: match diagnostic.severity {
+                TypstSeverity::Error => Severity::Error,
+                TypstSeverity::Warning => Severity::Warning,
+            },
+            location: location(world, markdown, diagnostic.span),
+            message: diagnostic.message.to_string(),
+            trace: diagnostic
+                .trace
+                .iter()
+                .map(|point| match location(world, markdown, point.span) {
+                    Some(location) => format!("{} at {location}", point.v),
+                    None => point.v.to_string(),
+                })
+                .collect(),
+            hints: diagnostic
+                .hints
+                .iter()
+                .map(|hint| hint.to_string())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Convert an error in the frontmatter or the theme settings of a zine, to report it like the
+/// Typst errors.
+///
+/// Other errors have no location in the zine, and give no diagnostics.
+pub fn error_diagnostics(basedir: &BaseDir, error: &Error) -> Vec<Diagnostic> {
+    let diagnostic = |location: Location, message: String| Diagnostic {
+        severity: Severity::Error,
+        location: Some(location),
+        message,
+        trace: Vec::new(),
+        hints: Vec::new(),
+    };
+
+    match error {
+        Error::InvalidFrontMatter {
+            path,
+            line,
+            column,
+            message,
+        } => vec![diagnostic(
+            Location {
+                file: basedir.join(path).path,
+                range: Some(
+                    Position {
+                        line: *line,
+                        column: *column,
+                    }
+                    .to_range(),
+                ),
+                frontmatter_key: None,
+            },
+            message.clone(),
+        )],
+        Error::UnclosedFrontMatter { path, .. } => vec![diagnostic(
+            Location {
+                file: basedir.join(path).path,
+                range: Some(Position { line: 1, column: 1 }.to_range()),
+                frontmatter_key: None,
+            },
+            error.to_string(),
+        )],
+        Error::NoTheme { path } => vec![diagnostic(
+            frontmatter_location(&basedir.join(path), "themes"),
+            error.to_string(),
+        )],
+        Error::ThemeSettings {
+            path,
+            theme,
+            problems,
+            origins,
+        } => problems
+            .iter()
+            .map(|problem| {
+                let key = format!("themes.{theme}.{}", problem.key());
+                // The setting may be inherited from a _defaults.toml
+                let file = origins
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| basedir.join(path));
+                diagnostic(
+                    frontmatter_location(&file, &key),
+                    format!("{problem} for theme {theme}"),
+                )
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Log diagnostics for humans, or print them to stdout as JSON (one object per line).
+pub fn print_diagnostics(diagnostics: &[Diagnostic], format: DiagnosticsFormat) {
+    for diagnostic in diagnostics {
+        match format {
+            DiagnosticsFormat::Human => match diagnostic.severity {
+                Severity::Error => error!("{diagnostic}"),
+                Severity::Warning => warn!("{diagnostic}"),
+            },
+            DiagnosticsFormat::Json => match serde_json::to_string(diagnostic) {
+                Ok(json) => println!("{json}"),
+                Err(e) => error!("Failed to serialize diagnostic {diagnostic:?}: {e}"),
+            },
+        }
+    }
+}
+
+/// The location of a span, in the markdown it was generated from if any.
+fn location(world: &dyn World, markdown: Option<&MarkdownSource>, span: Span) -> Option<Location> {
+    let id = span.id()?;
+    let source = world.source(id).ok()?;
+    let range = source.range(span)?;
+
+    if id == world.main() {
+        if let Some(markdown) = markdown {
+            // The generated Typst file doesn't exist on disk, so point at the markdown file
+            return Some(markdown.locate(range.start).unwrap_or_else(|| Location {
+                file: markdown.path.clone(),
+                range: None,
+                frontmatter_key: None,
+            }));
+        }
+    }
+
+    let position = |offset: usize| -> Option<Position> {
+        Some(Position {
+            line: source.byte_to_line(offset)? + 1,
+            column: source.byte_to_column(offset)? + 1,
+        })
+    };
+
+    Some(Location {
+        file: Utf8PathBuf::from(id.vpath().as_rootless_path().to_string_lossy().as_ref()),
+        range: Some(Range {
+            start: position(range.start)?,
+            end: position(range.end)?,
+        }),
+        frontmatter_key: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        path::temp_basedir,
+        theme::{ParamKind, SettingProblem},
+    };
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn json() {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            location: Some(Location {
+                file: "content/a/a.md".into(),
+                range: Some(Position { line: 3, column: 2 }.to_range()),
+                frontmatter_key: None,
+            }),
+            message: "unknown variable: foo".to_string(),
+            trace: Vec::new(),
+            hints: vec!["check the spelling".to_string()],
+        };
+
+        assert_eq!(
+            serde_json::to_string(&diagnostic).unwrap(),
+            "{\"severity\":\"error\",\"file\":\"content/a/a.md\",\
+             \"range\":{\"start\":{\"line\":3,\"column\":2},\"end\":{\"line\":3,\"column\":2}},\
+             \"message\":\"unknown variable: foo\",\"trace\":[],\"hints\":[\"check the spelling\"]}"
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "content/a/a.md:3:2: unknown variable: foo\n  hint: check the spelling"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let (_dir, basedir) = temp_basedir(&[
+            (
+                "content/a/a.md",
+                "+++\ntitle = \"A\"\n[themes.foo]\ncolor = 3\n+++\n",
+            ),
+            ("content/_defaults.toml", "[themes.foo]\nlogo = 3\n"),
+        ]);
+        let zine = basedir.join("content/a/a.md").absolute();
+        let defaults = basedir.join("content/_defaults.toml");
+
+        let diagnostics = error_diagnostics(
+            &basedir,
+            &Error::InvalidFrontMatter {
+                path: zine.clone(),
+                line: 2,
+                column: 9,
+                message: "expected a string".to_string(),
+            },
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "content/a/a.md:2:9: expected a string"
+        );
+
+        let diagnostics = error_diagnostics(
+            &basedir,
+            &Error::ThemeSettings {
+                path: zine.clone(),
+                theme: "foo".to_string(),
+                problems: vec![
+                    SettingProblem::WrongType {
+                        key: "color".to_string(),
+                        expected: ParamKind::Color,
+                    },
+                    SettingProblem::Missing("size".to_string()),
+                    SettingProblem::WrongType {
+                        key: "logo".to_string(),
+                        expected: ParamKind::Path,
+                    },
+                ],
+                origins: HashMap::from([("themes.foo.logo".to_string(), defaults)]),
+            },
+        );
+        let locations: Vec<String> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.location.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                "content/a/a.md:4:1 (frontmatter themes.foo.color)",
+                "content/a/a.md (frontmatter themes.foo.size)",
+                "content/_defaults.toml:2:1 (frontmatter themes.foo.logo)",
+            ]
+        );
+
+        let diagnostics = error_diagnostics(&basedir, &Error::NoTheme { path: zine.clone() });
+        assert_eq!(
+            diagnostics[0]
+                .location
+                .as_ref()
+                .unwrap()
+                .frontmatter_key
+                .as_deref(),
+            Some("themes")
+        );
+
+        assert!(
+            error_diagnostics(&basedir, &Error::UnknownTheme { name: "foo".into() }).is_empty()
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use camino::Utf8PathBuf;
 use snafu::prelude::*;
 
+use std::collections::HashMap;
+
+use crate::{diag::Diagnostic, path::RootPath, theme::SettingProblem};
+
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
 pub enum Error {
@@ -8,13 +12,36 @@ pub enum Error {
         "No basedir (containing content/themes folders) found in parent tree of {path}"
     ))]
     NoBaseDir { path: Utf8PathBuf },
+    #[snafu(display("Failed to read directory {path} due to error:\n{source}"))]
+    ReadDir {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{failed} out of {total} zines failed to build. See errors above."))]
+    Build { failed: usize, total: usize },
+    #[snafu(display("No theme selected to compile {path}. Check the themes in its frontmatter."))]
+    NoTheme { path: Utf8PathBuf },
     #[snafu(display("Typst compilation for {path} failed. See errors/warnings above."))]
-    Typst { path: Utf8PathBuf },
+    Typst {
+        path: Utf8PathBuf,
+        /// Errors and warnings from Typst
+        diagnostics: Vec<Diagnostic>,
+    },
+    #[snafu(display("Failed to create output directory {path} due to error:\n{source}"))]
+    OutputDir {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
     #[snafu(display("Failed to write PDF file to {path} due to error:\n{source}"))]
     PDFWrite {
         path: Utf8PathBuf,
         source: std::io::Error,
     },
+    #[snafu(display("Failed to write PNG file to {path} due to error:\n{source}"))]
+    PNGWrite {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
     #[snafu(display(
         "Failed to save Typst export from Markdown file to {path} due to error:\n{source}"
     ))]
@@ -22,4 +49,86 @@ pub enum Error {
         path: Utf8PathBuf,
         source: std::io::Error,
     },
+    #[snafu(display("Failed to read file {path} due to error:\n{source}"))]
+    ReadFile {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid theme manifest {path}:\n{source}"))]
+    ThemeManifest {
+        path: Utf8PathBuf,
+        source: toml::de::Error,
+    },
+    #[snafu(display(
+        "Invalid settings for theme {theme} in {path}:{}",
+        problem_list(problems)
+    ))]
+    ThemeSettings {
+        path: Utf8PathBuf,
+        theme: String,
+        problems: Vec<SettingProblem>,
+        /// The `_defaults.toml` of the settings the zine inherits, by frontmatter key
+        origins: HashMap<String, RootPath>,
+    },
+    #[snafu(display("Invalid defaults in theme manifest {path}:{}", problem_list(problems)))]
+    ThemeDefaults {
+        path: Utf8PathBuf,
+        problems: Vec<SettingProblem>,
+    },
+    #[snafu(display("Theme {theme} inherits from {parent}, which doesn't exist"))]
+    NoParentTheme { theme: String, parent: String },
+    #[snafu(display("Themes inherit from each other in a cycle: {chain}"))]
+    ThemeCycle { chain: String },
+    #[snafu(display("No theme named {name} in the basedir"))]
+    UnknownTheme { name: String },
+    #[snafu(display("Failed to create {path} due to error:\n{source}"))]
+    Scaffold {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid zine slug {slug:?}, it must be a folder name"))]
+    InvalidSlug { slug: String },
+    #[snafu(display("Choose a theme for the new zine with --theme among: {themes}"))]
+    ChooseTheme { themes: String },
+    #[snafu(display("Zine {path} already exists"))]
+    ZineExists { path: Utf8PathBuf },
+    #[snafu(display("Can only compile a .md or .typ file, not {path}"))]
+    UnsupportedFile { path: Utf8PathBuf },
+    #[snafu(display("Failed to access {path} due to error:\n{source}"))]
+    NoSuchPath {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Frontmatter in {path} is never closed by a {delimiter} line"))]
+    UnclosedFrontMatter {
+        path: Utf8PathBuf,
+        delimiter: String,
+    },
+    #[snafu(display("Invalid frontmatter defaults for {path}:\n{source}"))]
+    InvalidDefaults {
+        path: Utf8PathBuf,
+        source: toml::de::Error,
+    },
+    #[snafu(display("Invalid frontmatter in {path}:{line}:{column}: {message}"))]
+    InvalidFrontMatter {
+        path: Utf8PathBuf,
+        /// 1-indexed line in the markdown file
+        line: usize,
+        /// 1-indexed column in the markdown file
+        column: usize,
+        message: String,
+    },
+    #[snafu(display("Footnote {label:?} is referenced but its content was not found"))]
+    UndefinedFootnote { label: String },
+    #[snafu(display("Failed to set up the Typst world for {path}: {message}"))]
+    World { path: Utf8PathBuf, message: String },
+    #[snafu(display("Failed to export {path} to PDF: {message}"))]
+    PDFExport { path: Utf8PathBuf, message: String },
+    #[snafu(display("Failed to encode PNG for {path}: {message}"))]
+    PNGExport { path: Utf8PathBuf, message: String },
+}
+
+/// Lists setting problems one per line, below an error message
+fn problem_list(problems: &[SettingProblem]) -> String {
+    problems.iter().map(|p| format!("\n  - {p}")).collect()
 }
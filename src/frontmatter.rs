@@ -1,34 +1,47 @@
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 use serde::{Deserialize, Serialize};
-// use typst::foundations::{Repr, Value as TypstValue};
+use snafu::prelude::*;
 
 use std::collections::HashMap;
 
-use crate::{theme::Theme, typ::typst_escape, zine::ZineFile};
+use crate::{
+    error::*,
+    markdown_it::{first_heading, PrintLinks},
+    path::RootPath,
+    scaffold::title_from_slug,
+    srcmap::{line_column, Origin, SpanMap},
+    theme::{ParamKind, Theme},
+    typ::TypstValue,
+    zine::ZineFile,
+};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct FrontMatter {
+    /// Taken from the first heading when left out
+    #[serde(default)]
     pub title: String,
     pub subtitle: Option<String>,
     pub author: Option<String>,
     pub description: Option<String>,
     pub summary: Option<String>,
-    pub themes: HashMap<String, HashMap<String, String>>,
-    // themes: HashMap<String, HashMap<String, TypstValue>>,
+    /// How link URLs are printed on paper
+    #[serde(default)]
+    pub print_links: PrintLinks,
+    /// Settings passed to each theme, with their native TOML types
+    #[serde(default)]
+    pub themes: HashMap<String, toml::Table>,
+    /// The `_defaults.toml` file of each inherited value, by key like `themes.foo.logo`
+    #[serde(skip)]
+    pub origins: HashMap<String, RootPath>,
 }
 
 impl FrontMatter {
-    pub fn to_typst(&self, zine: &ZineFile, theme: &Theme) -> String {
-        let mut out = String::new();
-
-        out.push_str("title: \"");
-        out.push_str(&self.title);
-        out.push_str("\",\n");
+    /// Arguments passed to the theme's `zine` function, by name.
+    pub fn to_typst_args(&self, zine: &ZineFile, theme: &Theme) -> Vec<(String, TypstValue)> {
+        let mut args = vec![("title".to_string(), TypstValue::Str(self.title.clone()))];
 
         if let Some(subtitle) = &self.subtitle {
-            out.push_str("subtitle: \"");
-            out.push_str(&subtitle);
-            out.push_str("\",\n");
+            args.push(("subtitle".to_string(), TypstValue::Str(subtitle.clone())));
         }
 
         // out.push_str("border: \"");
@@ -36,57 +49,50 @@ impl FrontMatter {
         // out.push_str("\",\n");
 
         if let Some(author) = &self.author {
-            out.push_str("author: \"");
-            out.push_str(&typst_escape(&author));
-            out.push_str("\",\n");
+            args.push(("author".to_string(), TypstValue::Str(author.clone())));
         }
 
         if let Some(description) = &self.description {
-            out.push_str("description: [ ");
-            out.push_str(&typst_escape(&description));
-            out.push_str(" ],\n");
+            args.push((
+                "description".to_string(),
+                TypstValue::Content(description.clone()),
+            ));
         }
 
         if let Some(summary) = &self.summary {
-            out.push_str("summary: [ ");
-            out.push_str(&typst_escape(&summary));
-            out.push_str(" ],\n");
+            args.push(("summary".to_string(), TypstValue::Content(summary.clone())));
         }
 
-        // if let Some(theme_settings) = &self.themes.get(&theme.name) {
-        //     for (k, v) in theme_settings.iter() {
-        //         // out.push_str(&format!("{k}: \"{v}\",\n"));
-        //         // out.push_str(&format!("{k}: \"{}\",\n", v.clone().display().plain_text()));
-        //         // out.push_str(&format!("{k}: {},\n", v.clone().display().plain_text()));
-        //         info!("{k} has type {}", v.ty());
-        //         let stringy_value = match &v {
-        //             TypstValue::Color(_) => format!("rgb({})", v.clone().repr()),
-        //             _ => format!("{}", v.clone().repr()),
-        //         };
-        //         out.push_str(&format!("{k}: {stringy_value},\n"));
-        //     }
-        // }
-
-        if let Some(theme_settings) = &self.themes.get(&theme.name) {
+        let theme_settings = self.themes.get(&theme.name);
+        if let Some(theme_settings) = theme_settings {
             for (k, v) in theme_settings.iter() {
-                let typst_value = if k.ends_with("_color") {
-                    format!("rgb(\"{v}\")")
-                } else if k.ends_with("_size")
-                    || k.ends_with("_spacing")
-                    || k.ends_with("_bool")
-                    || k == "debug"
-                {
-                    format!("{v}")
-                } else if k.ends_with("_res") {
-                    format!(
-                        "\"{}\"",
-                        theme.zine_resource_relative_from_theme(v.as_str(), zine)
-                    )
-                } else {
-                    format!("\"{v}\"")
+                let origin = self.origins.get(&format!("themes.{}.{k}", theme.name));
+                args.push((k.clone(), theme_setting_value(k, v, origin, zine, theme)));
+            }
+        }
+
+        // Defaults from the theme manifest for the settings the zine leaves out
+        if let Some(manifest) = &theme.manifest {
+            for (k, param) in &manifest.params {
+                let Some(default) = &param.default else {
+                    continue;
                 };
+                if theme_settings.is_some_and(|settings| settings.contains_key(k)) {
+                    continue;
+                }
 
-                out.push_str(&format!("{k}: {typst_value},\n"));
+                let value = match (param.kind, default) {
+                    // Default paths are in the theme folder, or one of its parents
+                    (ParamKind::Path, toml::Value::String(path)) => TypstValue::Str(
+                        zine.file
+                            .root
+                            .join(theme.theme_resource_relative_from_basedir(path))
+                            .relative_to_theme(theme)
+                            .to_string(),
+                    ),
+                    (kind, value) => typed_value(kind, value),
+                };
+                args.push((k.clone(), value));
             }
         }
 
@@ -99,38 +105,669 @@ impl FrontMatter {
         //     out.push_str("\",\n");
         // }
 
+        args
+    }
+
+    pub fn to_typst(&self, zine: &ZineFile, theme: &Theme) -> String {
+        let mut out = String::new();
+
+        for (k, v) in self.to_typst_args(zine, theme) {
+            out.push_str(&format!("{k}: {},\n", v.to_typst()));
+        }
+
         out
     }
 
     pub fn with_typst_header(&self, zine: &ZineFile, theme: &Theme) -> String {
+        self.with_typst_header_mapped(zine, theme).0
+    }
+
+    /// The Typst header, with a map from every argument to the frontmatter key it comes from.
+    pub fn with_typst_header_mapped(&self, zine: &ZineFile, theme: &Theme) -> (String, SpanMap) {
         let relative_theme_path = theme.relative_to_zine(zine);
         debug!("Using import theme: {relative_theme_path}");
 
+        let mut spans = SpanMap::default();
+        spans.push(0, Origin::Generated);
+
         let mut out = String::new();
         out.push_str("#import \"");
         // out.push_str(theme.theme_relative().as_str());
         out.push_str(relative_theme_path.as_str());
         out.push_str("\": *");
+        out.push_str(&typst_hooks(&relative_theme_path));
         out.push_str("\n#show: zine.with(");
 
-        for line in self.to_typst(zine, theme).lines() {
-            out.push_str(&format!("  {line}\n"));
+        let theme_settings = self.themes.get(&theme.name);
+        for (k, v) in self.to_typst_args(zine, theme) {
+            let origin = if FRONTMATTER_ARGS.contains(&k.as_str()) {
+                Origin::FrontMatter(k.clone())
+            } else if theme_settings.is_some_and(|settings| settings.contains_key(&k)) {
+                Origin::FrontMatter(format!("themes.{}.{k}", theme.name))
+            } else {
+                // Default from the theme manifest
+                Origin::Generated
+            };
+            spans.push(out.len(), origin);
+
+            for line in format!("{k}: {},", v.to_typst()).lines() {
+                out.push_str(&format!("  {line}\n"));
+            }
         }
 
+        spans.push(out.len(), Origin::Generated);
         out.push_str(")\n\n");
 
-        out
+        (out, spans)
+    }
+}
+
+/// Frontmatter fields passed as arguments to the theme, with the same name.
+const FRONTMATTER_ARGS: &[&str] = &["title", "subtitle", "author", "description", "summary"];
+
+/// Convert a theme setting to a Typst value.
+///
+/// The type declared in the theme manifest is used if there's one. Otherwise, native TOML types
+/// are kept and strings may become lengths or colors (see [`TypstValue::from`]).
+///
+/// Paths point to a resource next to the zine, or next to the `_defaults.toml` setting them, and
+/// are made relative to the theme that will load them.
+fn theme_setting_value(
+    key: &str,
+    value: &toml::Value,
+    origin: Option<&RootPath>,
+    zine: &ZineFile,
+    theme: &Theme,
+) -> TypstValue {
+    let kind = match theme.param(key) {
+        Some(param) => param.kind,
+        None => {
+            if let Some(kind) = value.as_str().and_then(looks_typed) {
+                warn!(
+                    "Setting {key} of theme {} in {} is passed as a string, but looks like a {kind}. \
+                     Use a native TOML value, or declare its type in theme.toml.",
+                    theme.name,
+                    zine.file.absolute()
+                );
+            }
+            ParamKind::Any
+        }
+    };
+
+    match (kind, value) {
+        (ParamKind::Path, toml::Value::String(path)) => match origin {
+            Some(defaults) => {
+                TypstValue::Str(defaults.sibling(path).relative_to_theme(theme).to_string())
+            }
+            None => TypstValue::Str(
+                theme
+                    .zine_resource_relative_from_theme(path, zine)
+                    .to_string(),
+            ),
+        },
+        (kind, value) => typed_value(kind, value),
+    }
+}
+
+/// The type an undeclared string setting probably meant, like `bool` for `"true"`.
+///
+/// Lengths and colors are not listed, they are taken from strings anyway.
+fn looks_typed(value: &str) -> Option<ParamKind> {
+    if value.parse::<bool>().is_ok() {
+        Some(ParamKind::Bool)
+    } else if value.parse::<i64>().is_ok() {
+        Some(ParamKind::Int)
+    } else if value.parse::<f64>().is_ok() {
+        Some(ParamKind::Float)
+    } else {
+        None
+    }
+}
+
+/// Convert a TOML value to the Typst value for a declared type.
+fn typed_value(kind: ParamKind, value: &toml::Value) -> TypstValue {
+    match (kind, value) {
+        (ParamKind::String | ParamKind::Path, toml::Value::String(s)) => TypstValue::Str(s.clone()),
+        (ParamKind::Content, toml::Value::String(s)) => TypstValue::Content(s.clone()),
+        (ParamKind::Length, toml::Value::String(s)) => {
+            TypstValue::length(s).unwrap_or_else(|| TypstValue::Str(s.clone()))
+        }
+        (ParamKind::Color, toml::Value::String(s)) => {
+            TypstValue::color(s).unwrap_or_else(|| TypstValue::Str(s.clone()))
+        }
+        (ParamKind::Float, toml::Value::Integer(i)) => TypstValue::Float(*i as f64),
+        (_, value) => TypstValue::from(value),
+    }
+}
+
+/// Functions used by the generated Typst, which a theme may override by exporting them.
+///
+/// The theme is imported a second time as a module, to look for its overrides.
+const TYPST_HOOKS: &[(&str, &str)] = &[(
+    "zine_quote",
+    "(body, attribution: none) => quote(block: true, attribution: attribution, body)",
+)];
+
+fn typst_hooks(relative_theme_path: &Utf8Path) -> String {
+    let mut out = format!("\n#import \"{relative_theme_path}\" as zinifier-theme");
+
+    for (name, default) in TYPST_HOOKS {
+        out.push_str(&format!(
+            "\n#let {name} = dictionary(zinifier-theme).at(\"{name}\", default: {default})"
+        ));
+    }
+
+    out
+}
+
+/// Read the frontmatter and markdown of a zine, see [`load_frontmatter`].
+pub fn split_frontmatter(zine: &RootPath) -> Result<(FrontMatter, String), Error> {
+    let file = zine.absolute();
+    let content = std::fs::read_to_string(&file).context(ReadFileSnafu { path: file })?;
+    load_frontmatter(zine, &content)
+}
+
+/// Name of the files holding frontmatter defaults for the zines in their folder and below.
+pub const DEFAULTS_FILE: &str = "_defaults.toml";
+
+/// Split the frontmatter from the markdown of a zine, and fill in what it leaves out.
+///
+/// The `_defaults.toml` files from the basedir root down to the folder of the zine are
+/// deep-merged, each overriding the previous ones, and the frontmatter of the zine overrides them
+/// all. Tables, like the settings of a theme, are merged key by key, but listing `themes` replaces
+/// the inherited ones (see [`merge_frontmatter`]).
+///
+/// This is how a folder sets the theme of its zines: with `themes` in its `_defaults.toml`.
+/// Without themes there or in the zine, the zine uses the default theme of the basedir (see
+/// [`BaseDir::default_theme`](crate::path::BaseDir::default_theme)).
+pub fn load_frontmatter(zine: &RootPath, content: &str) -> Result<(FrontMatter, String), Error> {
+    let file = zine.absolute();
+    let (own, markdown) = parse_frontmatter_table(content, &file)?;
+
+    let mut table = toml::Table::new();
+    let mut origins = HashMap::new();
+    for defaults in defaults_files(zine) {
+        if defaults.is_file() {
+            debug!("Using defaults from {defaults} for {file}");
+            let defaults_table = read_defaults(&defaults)?;
+            for key in leaf_keys(&defaults_table) {
+                origins.insert(key, zine.root.join(&defaults));
+            }
+            merge_frontmatter(&mut table, defaults_table);
+        }
+    }
+    for key in leaf_keys(&own) {
+        origins.remove(&key);
+    }
+    merge_frontmatter(&mut table, own);
+
+    let mut frontmatter = frontmatter_from_table(table, markdown, &file)?;
+    frontmatter.origins = origins;
+    if frontmatter.themes.is_empty() {
+        if let Some(theme) = zine.root.default_theme()? {
+            debug!("Using default theme {theme} for {file}");
+            frontmatter.themes.insert(theme, toml::Table::new());
+        }
+    }
+
+    Ok((frontmatter, markdown.to_string()))
+}
+
+/// The `_defaults.toml` files which may apply to a zine, from the basedir root to its folder.
+pub fn defaults_files(zine: &RootPath) -> Vec<Utf8PathBuf> {
+    let mut files: Vec<Utf8PathBuf> = zine
+        .path
+        .parent()
+        .into_iter()
+        .flat_map(|dir| dir.ancestors())
+        .map(|dir| zine.root.join(dir.join(DEFAULTS_FILE)).absolute())
+        .collect();
+    files.reverse();
+    files
+}
+
+fn read_defaults(path: &Utf8Path) -> Result<toml::Table, Error> {
+    let content = std::fs::read_to_string(path).context(ReadFileSnafu { path })?;
+    // Deserialize as a FrontMatter first, to report wrong types where they are
+    toml::from_str::<FrontMatter>(&content).context(InvalidDefaultsSnafu { path })?;
+    toml::from_str(&content).context(InvalidDefaultsSnafu { path })
+}
+
+/// Merge the frontmatter `over` into `base`, like [`merge_tables`] except for the themes.
+///
+/// When `over` lists themes, only those are used: the inherited settings of a theme listed in both
+/// are kept, but the other inherited themes are dropped.
+fn merge_frontmatter(base: &mut toml::Table, mut over: toml::Table) {
+    if let Some(toml::Value::Table(themes)) = over.remove("themes") {
+        let mut inherited = match base.remove("themes") {
+            Some(toml::Value::Table(inherited)) => inherited,
+            _ => toml::Table::new(),
+        };
+        inherited.retain(|name, _| themes.contains_key(name));
+        merge_tables(&mut inherited, themes);
+        base.insert("themes".to_string(), inherited.into());
+    }
+
+    merge_tables(base, over);
+}
+
+/// Keys of the values in a table, down nested tables, like `themes.foo.logo`.
+fn leaf_keys(table: &toml::Table) -> Vec<String> {
+    table
+        .iter()
+        .flat_map(|(key, value)| match value {
+            toml::Value::Table(table) => leaf_keys(table)
+                .into_iter()
+                .map(|nested| format!("{key}.{nested}"))
+                .collect(),
+            _ => vec![key.clone()],
+        })
+        .collect()
+}
+
+/// Merge `over` into `base`, recursively for tables found in both.
+fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge_tables(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Split the frontmatter from the markdown of a zine already read from `file`.
+///
+/// The frontmatter is either TOML delimited by `+++` or YAML delimited by `---`. It may also be
+/// missing altogether. Without a title, the first heading of the markdown is used, or else the
+/// name of the file.
+pub fn parse_frontmatter(content: &str, file: &Utf8Path) -> Result<(FrontMatter, String), Error> {
+    let (table, markdown) = parse_frontmatter_table(content, file)?;
+    let frontmatter = frontmatter_from_table(table, markdown, file)?;
+    Ok((frontmatter, markdown.to_string()))
+}
+
+/// Split the frontmatter from the markdown, as a table to be merged with defaults.
+fn parse_frontmatter_table<'a>(
+    content: &'a str,
+    file: &Utf8Path,
+) -> Result<(toml::Table, &'a str), Error> {
+    if opens_with(content, "+++") {
+        let (toml_content, markdown_content) =
+            split_delimited(content, "+++").context(UnclosedFrontMatterSnafu {
+                path: file,
+                delimiter: "+++",
+            })?;
+
+        let located = |e: toml::de::Error| {
+            // The TOML starts right after the opening +++
+            let offset = "+++".len() + e.span().map(|span| span.start).unwrap_or_default();
+            invalid_frontmatter(content, file, offset, e.message())
+        };
+        // Deserialize as a FrontMatter first, to report wrong types where they are
+        toml::from_str::<FrontMatter>(toml_content).map_err(located)?;
+        Ok((
+            toml::from_str(toml_content).map_err(located)?,
+            markdown_content,
+        ))
+    } else if opens_with(content, "---") {
+        let (yaml_content, markdown_content) =
+            split_delimited(content, "---").context(UnclosedFrontMatterSnafu {
+                path: file,
+                delimiter: "---",
+            })?;
+
+        let located = |e: serde_yaml::Error| {
+            // The YAML starts right after the opening ---
+            let offset = "---".len() + e.location().map(|l| l.index()).unwrap_or_default();
+            invalid_frontmatter(content, file, offset, &e.to_string())
+        };
+        // The markdown may start with a thematic break instead of a frontmatter
+        let yaml = match serde_yaml::from_str::<serde_yaml::Value>(yaml_content) {
+            Ok(yaml @ (serde_yaml::Value::Mapping(_) | serde_yaml::Value::Null)) => yaml,
+            Ok(_) => {
+                debug!("No frontmatter in {file}, the --- block is not a YAML mapping");
+                return Ok((toml::Table::new(), content));
+            }
+            Err(e) => {
+                warn!("No frontmatter in {file}, the --- block is not valid YAML: {e}");
+                return Ok((toml::Table::new(), content));
+            }
+        };
+        serde_yaml::from_str::<FrontMatter>(yaml_content).map_err(located)?;
+        let table = match yaml_to_toml(yaml) {
+            Some(toml::Value::Table(table)) => table,
+            // An empty frontmatter
+            _ => toml::Table::new(),
+        };
+        Ok((table, markdown_content))
+    } else {
+        Ok((toml::Table::new(), content))
     }
 }
 
-pub fn split_frontmatter(file: &Utf8Path) -> (FrontMatter, String) {
-    let content = std::fs::read_to_string(file).unwrap();
+/// Convert YAML to TOML, leaving out nulls which TOML doesn't have.
+fn yaml_to_toml(value: serde_yaml::Value) -> Option<toml::Value> {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(b.into()),
+        Value::Number(n) => n
+            .as_i64()
+            .map(toml::Value::from)
+            .or_else(|| n.as_f64().map(toml::Value::from)),
+        Value::String(s) => Some(s.into()),
+        Value::Sequence(seq) => Some(toml::Value::Array(
+            seq.into_iter().filter_map(yaml_to_toml).collect(),
+        )),
+        Value::Mapping(map) => Some(toml::Value::Table(
+            map.into_iter()
+                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), yaml_to_toml(v)?)))
+                .collect(),
+        )),
+        Value::Tagged(tagged) => yaml_to_toml(tagged.value),
+    }
+}
+
+/// The [`FrontMatter`] of a table, with the title taken from the markdown when left out.
+fn frontmatter_from_table(
+    table: toml::Table,
+    markdown: &str,
+    file: &Utf8Path,
+) -> Result<FrontMatter, Error> {
+    // Every part was checked on its own already, so this only fails on surprising merges
+    let mut frontmatter: FrontMatter = toml::Value::Table(table)
+        .try_into()
+        .context(InvalidDefaultsSnafu { path: file })?;
+
+    if frontmatter.title.is_empty() {
+        frontmatter.title = first_heading(markdown)
+            .unwrap_or_else(|| title_from_slug(file.file_stem().unwrap_or_default()));
+    }
+
+    Ok(frontmatter)
+}
+
+/// Whether the first line of `content` is a frontmatter `delimiter`.
+fn opens_with(content: &str, delimiter: &str) -> bool {
+    content.lines().next().map(str::trim_end) == Some(delimiter)
+}
+
+/// Split a frontmatter opened by `delimiter` at the start of `content` from the markdown after it.
+///
+/// The frontmatter is closed by a line with only the delimiter, not by `----` or `--- text`.
+fn split_delimited<'a>(content: &'a str, delimiter: &str) -> Option<(&'a str, &'a str)> {
+    let rest = content.strip_prefix(delimiter)?;
+
+    // The first line is the end of the opening delimiter
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if offset > 0 && line.trim_end() == delimiter {
+            return Some((&rest[..offset - 1], &rest[offset + delimiter.len()..]));
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+fn invalid_frontmatter(content: &str, file: &Utf8Path, offset: usize, message: &str) -> Error {
+    let (line, column) = line_column(content, offset);
+
+    Error::InvalidFrontMatter {
+        path: file.to_path_buf(),
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::{temp_basedir, BaseDir};
+
+    #[test]
+    fn frontmatter() {
+        let (frontmatter, markdown) = parse_frontmatter(
+            "+++\ntitle = \"Zine\"\n[themes.foo]\n+++\n# Zine\n",
+            "zine.md".into(),
+        )
+        .unwrap();
+        assert_eq!(frontmatter.title, "Zine");
+        assert_eq!(markdown, "\n# Zine\n");
+    }
+
+    #[test]
+    fn setting_types() {
+        let length = toml::Value::from("24pt");
+        assert_eq!(
+            typed_value(ParamKind::Length, &length),
+            TypstValue::Length("24pt".to_string())
+        );
+        assert_eq!(
+            typed_value(ParamKind::Any, &length),
+            TypstValue::Length("24pt".to_string())
+        );
+        assert_eq!(
+            typed_value(ParamKind::String, &length),
+            TypstValue::Str("24pt".to_string())
+        );
+        let color = toml::Value::from("#123");
+        assert_eq!(
+            typed_value(ParamKind::Color, &color),
+            TypstValue::Color("#123".to_string())
+        );
+        assert_eq!(
+            typed_value(ParamKind::String, &color),
+            TypstValue::Str("#123".to_string())
+        );
 
-    let (toml_content, markdown_content) = content
-        .trim_start_matches("+++")
-        .split_once("\n+++")
+        assert_eq!(looks_typed("true"), Some(ParamKind::Bool));
+        assert_eq!(looks_typed("2.5"), Some(ParamKind::Float));
+        assert_eq!(looks_typed("1em"), None);
+        assert_eq!(looks_typed("Libertinus Serif"), None);
+    }
+
+    #[test]
+    fn yaml_frontmatter() {
+        let (frontmatter, markdown) = parse_frontmatter(
+            "---\ntitle: Zine\ndate: 2024-01-01\nthemes:\n  foo:\n    columns: 2\n---\n# Zine\n",
+            "zine.md".into(),
+        )
         .unwrap();
-    let frontmatter = toml::from_str(&toml_content).unwrap();
+        assert_eq!(frontmatter.title, "Zine");
+        assert_eq!(frontmatter.themes["foo"]["columns"].as_integer(), Some(2));
+        assert_eq!(markdown, "\n# Zine\n");
+    }
+
+    #[test]
+    fn missing_frontmatter() {
+        let (frontmatter, markdown) =
+            parse_frontmatter("Intro\n\n## The *first* zine\n", "zine.md".into()).unwrap();
+        assert_eq!(frontmatter.title, "The first zine");
+        assert!(frontmatter.themes.is_empty());
+        assert_eq!(markdown, "Intro\n\n## The *first* zine\n");
+
+        let (frontmatter, _) = parse_frontmatter("Intro\n", "my-zine.md".into()).unwrap();
+        assert_eq!(frontmatter.title, "My zine");
+
+        assert!(matches!(
+            parse_frontmatter("+++\ntitle = \"Zine\"\n", "zine.md".into()),
+            Err(Error::UnclosedFrontMatter { .. })
+        ));
+        assert!(matches!(
+            parse_frontmatter("---\ntitle: Zine\n", "zine.md".into()),
+            Err(Error::UnclosedFrontMatter { .. })
+        ));
+    }
+
+    #[test]
+    fn closing_delimiter() {
+        assert_eq!(
+            split_delimited("---\na: 1\n----\nb: 2\n--- text\n---\nBody", "---"),
+            Some(("\na: 1\n----\nb: 2\n--- text", "\nBody"))
+        );
+        assert_eq!(split_delimited("+++\na = 1\n++++\n", "+++"), None);
+
+        assert!(matches!(
+            parse_frontmatter("+++\ntitle = \"Zine\"\n+++ end\n", "zine.md".into()),
+            Err(Error::UnclosedFrontMatter { .. })
+        ));
+    }
+
+    #[test]
+    fn thematic_break() {
+        // Not a YAML mapping, so the markdown starts with a thematic break
+        let content = "---\nJust a paragraph.\n\n---\n\n# Zine\n";
+        let (frontmatter, markdown) = parse_frontmatter(content, "zine.md".into()).unwrap();
+        assert_eq!(frontmatter.title, "Zine");
+        assert_eq!(markdown, content);
+
+        let content = "---\n- a\n- b\n---\n";
+        let (_, markdown) = parse_frontmatter(content, "zine.md".into()).unwrap();
+        assert_eq!(markdown, content);
+    }
+
+    #[test]
+    fn invalid_frontmatter() {
+        let res = parse_frontmatter(
+            "+++\ntitle = \"Zine\"\nauthor = Me\n+++\n",
+            "zine.md".into(),
+        );
+        let Err(Error::InvalidFrontMatter { line, column, .. }) = res else {
+            panic!("Expected an invalid frontmatter error, got {res:?}");
+        };
+        assert_eq!((line, column), (3, 10));
+
+        let res = parse_frontmatter("---\ntitle: Zine\nauthor: [Me]\n---\n", "zine.md".into());
+        let Err(Error::InvalidFrontMatter { line, .. }) = res else {
+            panic!("Expected an invalid frontmatter error, got {res:?}");
+        };
+        assert_eq!(line, 3);
+    }
 
-    (frontmatter, markdown_content.to_string())
+    #[test]
+    fn defaults() {
+        let zine = BaseDir::from(Utf8PathBuf::from("/root")).join("content/series/a/a.md");
+        assert_eq!(
+            defaults_files(&zine),
+            [
+                "/root/_defaults.toml",
+                "/root/content/_defaults.toml",
+                "/root/content/series/_defaults.toml",
+                "/root/content/series/a/_defaults.toml",
+            ]
+        );
+
+        let mut table: toml::Table = toml::from_str(
+            "author = \"Collective\"\n[themes.foo]\ncolor = \"#ff0000\"\ncolumns = 2\n",
+        )
+        .unwrap();
+        let own: toml::Table =
+            toml::from_str("title = \"Zine\"\n[themes.foo]\ncolumns = 3\n").unwrap();
+        merge_frontmatter(&mut table, own);
+
+        let frontmatter = frontmatter_from_table(table, "", "zine.md".into()).unwrap();
+        assert_eq!(frontmatter.title, "Zine");
+        assert_eq!(frontmatter.author.as_deref(), Some("Collective"));
+        assert_eq!(
+            frontmatter.themes["foo"],
+            toml::from_str::<toml::Table>("color = \"#ff0000\"\ncolumns = 3\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn inherited_paths() {
+        let basedir = BaseDir::from(Utf8PathBuf::from("/root"));
+        let file = basedir.join("content/series/a/a.md");
+        let zine = ZineFile {
+            source: typst::syntax::Source::detached(""),
+            file,
+            fonts: Vec::new(),
+            markdown: None,
+            files: Vec::new(),
+        };
+        let mut theme = Theme::new(&basedir, "foo");
+        theme.manifest =
+            Some(toml::from_str("name = \"foo\"\n[params.logo]\ntype = \"path\"\n").unwrap());
+
+        let own: toml::Table = toml::from_str("title = \"Zine\"\n[themes.foo]\n").unwrap();
+        let defaults: toml::Table = toml::from_str("[themes.foo]\nlogo = \"logo.png\"\n").unwrap();
+        assert_eq!(leaf_keys(&own), ["title"]);
+        assert_eq!(leaf_keys(&defaults), ["themes.foo.logo"]);
+
+        // Next to the _defaults.toml which sets it, not next to the zine
+        let logo = toml::Value::from("logo.png");
+        let origin = basedir.join("content/series/_defaults.toml");
+        assert_eq!(
+            theme_setting_value("logo", &logo, Some(&origin), &zine, &theme),
+            TypstValue::Str("../../content/series/logo.png".to_string())
+        );
+        assert_eq!(
+            theme_setting_value("logo", &logo, None, &zine, &theme),
+            TypstValue::Str("../../content/series/a/logo.png".to_string())
+        );
+    }
+
+    #[test]
+    fn folder_theme() {
+        let (_dir, basedir) = temp_basedir(&[
+            ("themes/default/theme.typ", ""),
+            ("themes/series/theme.typ", ""),
+            ("content/series/_defaults.toml", "[themes.series]\n"),
+            ("content/series/a/a.md", "# A\n"),
+            ("content/b/b.md", "# B\n"),
+        ]);
+
+        // The folder's defaults choose the theme, there's no frontmatter
+        let (frontmatter, _) = split_frontmatter(&basedir.join("content/series/a/a.md")).unwrap();
+        assert_eq!(frontmatter.title, "A");
+        assert_eq!(frontmatter.themes.keys().collect::<Vec<_>>(), ["series"]);
+
+        // Outside the folder, the default theme of the basedir
+        let (frontmatter, _) = split_frontmatter(&basedir.join("content/b/b.md")).unwrap();
+        assert_eq!(frontmatter.themes.keys().collect::<Vec<_>>(), ["default"]);
+    }
+
+    #[test]
+    fn defaults_themes() {
+        let mut table: toml::Table = toml::from_str(
+            "[themes.foo]
+columns = 2
+[themes.bar]
+columns = 4
+",
+        )
+        .unwrap();
+
+        // Without themes, the inherited ones are kept
+        merge_frontmatter(&mut table, toml::from_str("title = \"Zine\"\n").unwrap());
+        let frontmatter = frontmatter_from_table(table.clone(), "", "zine.md".into()).unwrap();
+        assert_eq!(frontmatter.themes.len(), 2);
+
+        // Listing themes drops the other inherited ones, and keeps their inherited settings
+        merge_frontmatter(
+            &mut table,
+            toml::from_str(
+                "[themes.foo]
+border = true
+",
+            )
+            .unwrap(),
+        );
+        let frontmatter = frontmatter_from_table(table, "", "zine.md".into()).unwrap();
+        assert!(!frontmatter.themes.contains_key("bar"));
+        assert_eq!(
+            frontmatter.themes["foo"],
+            toml::from_str::<toml::Table>(
+                "columns = 2
+border = true
+"
+            )
+            .unwrap()
+        );
+    }
 }
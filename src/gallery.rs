@@ -0,0 +1,234 @@
+use camino::Utf8Path;
+use rayon::prelude::*;
+use snafu::prelude::*;
+use tiny_skia::{Color, Pixmap, PixmapPaint, Transform};
+use typst::{
+    foundations::Bytes,
+    syntax::{FileId, Source, VirtualPath},
+};
+use typst_library::layout::Size;
+
+use crate::{
+    error::*,
+    path::BaseDir,
+    theme::{ParamKind, Theme},
+    typ::{typst_escape, typst_string, CompileOptions, DiagnosticsFormat},
+    zine::{CompiledZine, ZineFile},
+};
+
+/// Markdown of the sample zine rendered with every theme.
+const SAMPLE: &str = include_str!("gallery.md");
+
+/// Folder where the sample zine pretends to be, relative to the basedir.
+const SAMPLE_DIR: &str = "content/zinifier-gallery";
+
+/// Image for the required path settings of the sample, next to it.
+const PLACEHOLDER_IMAGE: &str = "placeholder.png";
+const PLACEHOLDER_IMAGE_BYTES: &[u8] = include_bytes!("placeholder.png");
+
+/// Space around and between pages in the PNG contact sheet, in pixels.
+const GAP: u32 = 24;
+
+#[derive(Copy, Clone, Debug, Default, clap::ValueEnum)]
+pub enum GalleryFormat {
+    /// A single image with the first pages in a grid
+    #[default]
+    Png,
+    /// A PDF with the first page of every theme
+    Pdf,
+}
+
+/// Render the sample zine with every theme in the [`BaseDir`], and write a contact sheet of the
+/// first pages to `output` (relative to the basedir, the extension is added).
+///
+/// Themes are in alphabetical order. Broken themes are skipped, with a page saying why.
+pub fn gallery(basedir: &BaseDir, format: GalleryFormat, output: &Utf8Path) -> Result<(), Error> {
+    let names = basedir.themes()?;
+
+    let rendered: Vec<Result<CompiledZine, Error>> = names
+        .par_iter()
+        .map(|name| render_sample(basedir, name))
+        .collect();
+
+    let rendered_count = rendered.iter().filter(|zine| zine.is_ok()).count();
+    ensure!(
+        rendered_count > 0,
+        NoThemeSnafu {
+            path: basedir.join("themes").absolute(),
+        }
+    );
+    info!(
+        "Rendered the sample zine with {rendered_count} out of {} themes",
+        names.len()
+    );
+
+    // Skipped themes get a page of the same size as the others
+    let size = rendered
+        .iter()
+        .find_map(|zine| zine.as_ref().ok()?.inner.pages.first())
+        .map(|page| page.frame.size());
+    let mut zines = Vec::new();
+    for (name, zine) in names.iter().zip(rendered) {
+        match zine {
+            Ok(zine) => zines.push(zine),
+            Err(e) => {
+                error!("Skipping theme {name}: {e}");
+                zines.push(skipped_page(basedir, name, &e, size)?);
+            }
+        }
+    }
+
+    let output = basedir.join(output);
+    match format {
+        GalleryFormat::Png => {
+            let sheet = contact_sheet(&zines);
+            let out = output.output(None, "png");
+            let png = sheet.encode_png().map_err(|e| Error::PNGExport {
+                path: out.clone(),
+                message: e.to_string(),
+            })?;
+            std::fs::write(&out, &png).context(PNGWriteSnafu { path: out.clone() })?;
+            info!("Wrote {out}");
+        }
+        GalleryFormat::Pdf => {
+            let mut document = zines[0].inner.clone();
+            document.pages = zines
+                .iter()
+                .filter_map(|zine| zine.inner.pages.first().cloned())
+                .enumerate()
+                .map(|(i, mut page)| {
+                    page.number = i + 1;
+                    page
+                })
+                .collect();
+
+            CompiledZine {
+                source: output.clone(),
+                inner: document,
+                warnings: Vec::new(),
+            }
+            .to_pdf(None)?;
+            info!("Wrote {}", output.output(None, "pdf"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Compile the sample zine with a theme, filling its required settings with placeholders.
+///
+/// Required paths point to a placeholder image, served from memory next to the sample.
+fn render_sample(basedir: &BaseDir, name: &str) -> Result<CompiledZine, Error> {
+    let theme = Theme::load(basedir, name)?;
+    let mut settings = toml::Table::new();
+    if let Some(manifest) = &theme.manifest {
+        settings = manifest.placeholder_settings();
+        for (key, param) in &manifest.params {
+            if param.required && param.kind == ParamKind::Path {
+                settings.insert(key.clone(), PLACEHOLDER_IMAGE.into());
+            }
+        }
+    }
+
+    let mut themes = toml::Table::new();
+    themes.insert(name.to_string(), toml::Value::Table(settings));
+    let mut frontmatter = toml::Table::new();
+    frontmatter.insert("title".to_string(), "Zines".into());
+    frontmatter.insert("subtitle".to_string(), name.into());
+    frontmatter.insert("author".to_string(), "zinifier".into());
+    frontmatter.insert("themes".to_string(), toml::Value::Table(themes));
+
+    // The sample pretends to be in the content folder, so that theme paths resolve
+    let file = basedir.join(format!("{SAMPLE_DIR}/zinifier-gallery.md"));
+    let zine = ZineFile {
+        source: Source::new(
+            FileId::new_fake(VirtualPath::new(file.path.as_std_path())),
+            format!("+++\n{frontmatter}+++\n{SAMPLE}"),
+        ),
+        file,
+        fonts: theme.fonts(),
+        markdown: None,
+        files: vec![(
+            basedir.join(format!("{SAMPLE_DIR}/{PLACEHOLDER_IMAGE}")),
+            Bytes::new(PLACEHOLDER_IMAGE_BYTES),
+        )],
+    };
+
+    let options = CompileOptions {
+        themes: vec![name.to_string()],
+        ..Default::default()
+    };
+    let mut compiled = zine.compile_md(&options)?;
+    // Only the requested theme is compiled
+    Ok(compiled.remove(0))
+}
+
+/// A page for a theme which could not render the sample, with the error.
+fn skipped_page(
+    basedir: &BaseDir,
+    name: &str,
+    error: &Error,
+    size: Option<Size>,
+) -> Result<CompiledZine, Error> {
+    let mut source = match size {
+        Some(size) => format!(
+            "#set page(width: {}pt, height: {}pt)\n",
+            size.x.to_pt(),
+            size.y.to_pt()
+        ),
+        None => "#set page(\"a5\")\n".to_string(),
+    };
+    source.push_str(&format!(
+        "= {}\n\nThis theme was skipped:\n\n#raw({}, block: true)\n",
+        typst_escape(name),
+        typst_string(&error.to_string())
+    ));
+
+    let file = basedir.join(format!("{SAMPLE_DIR}/zinifier-skipped-{name}.typ"));
+    let zine = ZineFile {
+        source: Source::new(
+            FileId::new_fake(VirtualPath::new(file.path.as_std_path())),
+            source,
+        ),
+        file,
+        fonts: Vec::new(),
+        markdown: None,
+        files: Vec::new(),
+    };
+    zine.compile(DiagnosticsFormat::Human)
+}
+
+/// Draw the first page of every zine in a grid, as square as possible.
+fn contact_sheet(zines: &[CompiledZine]) -> Pixmap {
+    let pages: Vec<Pixmap> = zines
+        .iter()
+        .filter_map(|zine| zine.first_pixmap())
+        .collect();
+
+    let columns = (pages.len() as f64).sqrt().ceil().max(1.0) as u32;
+    let rows = (pages.len() as u32).div_ceil(columns).max(1);
+    let cell_width = pages.iter().map(|p| p.width()).max().unwrap_or(1);
+    let cell_height = pages.iter().map(|p| p.height()).max().unwrap_or(1);
+
+    // Only fails for an empty or huge size
+    let mut sheet = Pixmap::new(
+        columns * (cell_width + GAP) + GAP,
+        rows * (cell_height + GAP) + GAP,
+    )
+    .unwrap();
+    sheet.fill(Color::from_rgba8(0xdd, 0xdd, 0xdd, 0xff));
+
+    for (i, page) in pages.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        sheet.draw_pixmap(
+            (GAP + column * (cell_width + GAP)) as i32,
+            (GAP + row * (cell_height + GAP)) as i32,
+            page.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+    }
+
+    sheet
+}
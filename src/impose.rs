@@ -0,0 +1,117 @@
+use typst_library::layout::{Angle, Frame, PagedDocument, Point, Size, Transform};
+
+/// How the printer turns the sheet over when printing the back side.
+#[derive(Copy, Clone, Debug, Default, clap::ValueEnum)]
+pub enum Duplex {
+    /// Sheets are flipped on their short edge, back sides are printed as-is
+    #[default]
+    ShortEdge,
+    /// Sheets are flipped on their long edge, back sides need to be rotated upside down
+    LongEdge,
+}
+
+/// Compute the saddle-stitch page order for a booklet of `pages` pages.
+///
+/// `pages` must be a multiple of 4. Every pair is (left, right) for one side of a sheet,
+/// alternating front and back, with 1-indexed page numbers. For 8 pages, this produces
+/// `[(8, 1), (2, 7), (6, 3), (4, 5)]`.
+pub fn booklet_order(pages: usize) -> Vec<(usize, usize)> {
+    assert!(pages % 4 == 0, "Booklet page count must be a multiple of 4");
+
+    let mut order = Vec::with_capacity(pages / 2);
+    for sheet in 0..pages / 4 {
+        // Front side
+        order.push((pages - 2 * sheet, 2 * sheet + 1));
+        // Back side
+        order.push((2 * sheet + 2, pages - 2 * sheet - 1));
+    }
+
+    order
+}
+
+/// Impose a document for saddle-stitch binding.
+///
+/// Pages are padded with blank pages to a multiple of 4, then placed two-up on sheets twice as
+/// wide as the widest page, so that the printed stack can be folded in half and stapled.
+pub fn saddle_stitch(document: &PagedDocument, duplex: Duplex) -> PagedDocument {
+    let Some(first) = document.pages.first() else {
+        return document.clone();
+    };
+
+    // All pages are placed in slots as big as the biggest page
+    let slot = document.pages.iter().fold(Size::zero(), |slot, page| {
+        Size::new(
+            slot.x.max(page.frame.width()),
+            slot.y.max(page.frame.height()),
+        )
+    });
+    let sheet_size = Size::new(slot.x * 2.0, slot.y);
+
+    let padded = document.pages.len().div_ceil(4) * 4;
+    debug!(
+        "Imposing {} pages ({} with padding) on {} sheets",
+        document.pages.len(),
+        padded,
+        padded / 4
+    );
+
+    let page_frame = |number: usize| -> Frame {
+        match document.pages.get(number - 1) {
+            Some(page) => page.frame.clone(),
+            None => Frame::hard(slot),
+        }
+    };
+
+    let mut imposed = document.clone();
+    imposed.pages = booklet_order(padded)
+        .into_iter()
+        .enumerate()
+        .map(|(side, (left, right))| {
+            let mut sheet = Frame::hard(sheet_size);
+            sheet.push_frame(Point::zero(), page_frame(left));
+            sheet.push_frame(Point::with_x(slot.x), page_frame(right));
+
+            // Odd sides are the back of a sheet
+            if side % 2 == 1 {
+                if let Duplex::LongEdge = duplex {
+                    sheet.transform(
+                        Transform::translate(sheet_size.x, sheet_size.y)
+                            .pre_concat(Transform::rotate(Angle::deg(180.0))),
+                    );
+                }
+            }
+
+            let mut page = first.clone();
+            page.frame = sheet;
+            page.number = side + 1;
+            page
+        })
+        .collect();
+
+    imposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eight_pages() {
+        assert_eq!(booklet_order(8), vec![(8, 1), (2, 7), (6, 3), (4, 5)]);
+    }
+
+    #[test]
+    fn four_pages() {
+        assert_eq!(booklet_order(4), vec![(4, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn every_page_once() {
+        let mut pages: Vec<usize> = booklet_order(16)
+            .into_iter()
+            .flat_map(|(left, right)| [left, right])
+            .collect();
+        pages.sort();
+        assert_eq!(pages, (1..=16).collect::<Vec<usize>>());
+    }
+}
@@ -3,12 +3,18 @@ extern crate log;
 
 #[cfg(feature = "cli")]
 pub mod cli;
+pub mod diag;
 pub mod error;
 pub mod frontmatter;
+pub mod gallery;
+pub mod impose;
 pub mod markdown_it;
 pub mod path;
+pub mod scaffold;
+pub mod srcmap;
 pub mod theme;
 pub mod typ;
 #[cfg(feature = "watch")]
 pub mod watch;
+pub mod world;
 pub mod zine;
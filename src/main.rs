@@ -1,17 +1,20 @@
 use camino::Utf8PathBuf;
 use clap::Parser;
 
+use snafu::prelude::*;
+
 use zinifier::{
-    cli::{Action, SourceType},
-    path::RootPath,
+    cli::{build, themes, Action, SourceType},
+    error::*,
+    gallery::gallery,
+    path::{BaseDir, RootPath},
+    scaffold::{init, new_zine},
 };
 
 #[derive(Debug, Parser)]
 struct Cli {
+    #[clap(subcommand)]
     action: Action,
-    #[clap(short, long, default_value = "pdf")]
-    mode: zinifier::typ::CompileMode,
-    file: Utf8PathBuf,
 }
 
 fn main() {
@@ -23,22 +26,67 @@ fn main() {
 
     let cli = Cli::parse();
 
-    let absolute_file = cli.file.canonicalize_utf8().unwrap();
-    let s = SourceType::from_ext(&absolute_file);
-    log::trace!("fun");
-
-    // Deduce BaseDir and relative path
-    let file = RootPath::from_path(&absolute_file).unwrap();
-    log::trace!("fun");
-
-    let res = match &cli.action {
-        Action::Compile => s.compile(&file, cli.mode),
-        #[cfg(feature = "watch")]
-        Action::Watch => s.watch(&file),
-    };
+    let res = run(&cli.action);
 
     if let Err(e) = res {
         eprintln!("{}", e);
         std::process::exit(1);
     }
 }
+
+fn run(action: &Action) -> Result<(), Error> {
+    let absolute = |path: &Utf8PathBuf| {
+        path.canonicalize_utf8()
+            .context(NoSuchPathSnafu { path: path.clone() })
+    };
+
+    match action {
+        Action::Build { options, path } => {
+            let basedir = BaseDir::from_path(&absolute(path)?)?;
+            build(&basedir, options)
+        }
+        Action::Compile { options, file } => {
+            let absolute_file = absolute(file)?;
+            let s = SourceType::from_ext(&absolute_file)?;
+
+            // Deduce BaseDir and relative path
+            let file = RootPath::from_path(&absolute_file)?;
+            s.compile(&file, options)
+        }
+        #[cfg(feature = "watch")]
+        Action::Watch { options, file } => {
+            let absolute_file = absolute(file)?;
+            let s = SourceType::from_ext(&absolute_file)?;
+
+            // Deduce BaseDir and relative path
+            let file = RootPath::from_path(&absolute_file)?;
+            s.watch(&file, options)
+        }
+        Action::Gallery {
+            format,
+            output,
+            path,
+        } => {
+            let basedir = BaseDir::from_path(&absolute(path)?)?;
+            gallery(&basedir, *format, output)
+        }
+        Action::Init { path } => {
+            let basedir = init(path)?;
+            log::info!("Created basedir {basedir}, add zines with `zinifier new <slug>`");
+            Ok(())
+        }
+        Action::New {
+            slug,
+            theme,
+            basedir,
+        } => {
+            let basedir = BaseDir::from_path(&absolute(basedir)?)?;
+            new_zine(&basedir, slug, theme.as_deref())?;
+            Ok(())
+        }
+        Action::Themes { action, basedir } => {
+            let basedir = BaseDir::from_path(&absolute(basedir)?)?;
+            themes(&basedir, action)
+        }
+    }
+}
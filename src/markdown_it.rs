@@ -2,16 +2,27 @@
 use markdown_it::parser::{block::*, inline::*};
 use markdown_it::plugins::cmark::{
     block::{
+        blockquote::Blockquote,
+        code::CodeBlock,
+        fence::CodeFence,
         heading::ATXHeading,
-        list::{BulletList, ListItem, OrderedList},
+        lheading::SetextHeading,
+        list::{BulletList, OrderedList},
         paragraph::Paragraph,
     },
     inline::{
-        emphasis::Strong,
+        autolink::Autolink,
+        backticks::CodeInline,
+        emphasis::{Em, Strong},
         image::Image,
+        link::Link,
         newline::{Hardbreak, Softbreak},
     },
 };
+use markdown_it::plugins::extra::{
+    strikethrough::Strikethrough,
+    tables::{ColumnAlignment, Table, TableHead},
+};
 use markdown_it::{MarkdownIt, Node, NodeValue, Renderer};
 use markdown_it_footnote::{
     definitions::FootnoteDefinition, inline::InlineFootnote, references::FootnoteReference,
@@ -20,55 +31,298 @@ use markdownmacros::{
     block::{parse_block, parse_block_end, BlockMacro},
     // value::Value,
 };
+use serde::{Deserialize, Serialize};
+
+use snafu::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::{
+    error::*,
+    srcmap::{Origin, SpanMap},
+    typ::{is_typst_length, typst_escape, typst_string},
+};
+
+/// How the URL of a link is printed, because a link can't be clicked on paper.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintLinks {
+    /// Only print the link text
+    #[default]
+    None,
+    /// Print the URL in a footnote
+    Footnote,
+    /// Print the URL in parentheses after the link text
+    Inline,
+}
+
+/// State of the conversion for a whole document, shared with nested content.
+#[derive(Default)]
+struct ConversionState {
+    print_links: PrintLinks,
+    /// Footnote references in the output, by definition id, with their label
+    footnote_refs: Vec<(usize, Option<String>)>,
+    // The first optional string is the label (like NdT for translation notes)
+    // If only a number is in the label, it is ignored.
+    footnotes: HashMap<usize, (Option<String>, String)>,
+    /// Markdown offsets of the markers in the output (see [`mark_origin`])
+    origins: Vec<usize>,
+    /// Number of [`node_to_typst`] calls in progress, more than one inside containers
+    nesting: usize,
+    /// First error while converting nested markdown, returned once the walk is over
+    error: Option<Error>,
+}
+
+/// Start and end of a marker in the output, holding the index of an origin in
+/// [`ConversionState::origins`]. These private use characters are not expected in markdown.
+const MARKER_START: char = '\u{F0000}';
+const MARKER_END: char = '\u{F0001}';
+
+/// Start and end of a footnote placeholder, holding the definition id of the footnote (see
+/// [`footnote_placeholder`]).
+const FOOTNOTE_START: char = '\u{F0002}';
+const FOOTNOTE_END: char = '\u{F0003}';
 
-use crate::typ::typst_escape;
+pub fn markdown_to_typst_content(markdown: &str, print_links: PrintLinks) -> Result<String, Error> {
+    Ok(markdown_to_typst_content_mapped(markdown, print_links)?.0)
+}
 
-enum ListType {
-    Numbered,
-    NotNumbered,
+/// Convert markdown to Typst, with a map of where the Typst comes from in the markdown.
+///
+/// While converting, markers are inserted in the output before every node, so that they follow
+/// the content when it's moved or indented. They are then removed and turned into a [`SpanMap`].
+pub fn markdown_to_typst_content_mapped(
+    markdown: &str,
+    print_links: PrintLinks,
+) -> Result<(String, SpanMap), Error> {
+    let state = ConversionState {
+        print_links,
+        ..Default::default()
+    };
+    convert(markdown, state)
 }
 
-pub fn markdown_to_typst_content(markdown: &str) -> String {
+/// Convert a markdown document, which may be nested inside other content (see
+/// [`ConversionState::nesting`]).
+fn convert(markdown: &str, mut state: ConversionState) -> Result<(String, SpanMap), Error> {
     let md = &mut MarkdownIt::new();
     markdown_it::plugins::cmark::add(md);
+    markdown_it::plugins::extra::strikethrough::add(md);
+    markdown_it::plugins::extra::tables::add(md);
     markdown_it_footnote::add(md);
     md.block.add_rule::<BlockMacroScanner>();
 
-    // Context for the current list items
-    let mut list_type = ListType::NotNumbered;
-
     let mut root = md.parse(markdown);
 
-    let mut out = String::new();
-
     // We walk mutably so we can consume inner children
     // However, we always need a second pass because footnotes are linear in MarkdownIt AST (first the ref, then the rest of document,
     // then the actual footnote content).
-    let mut footnote_counter = 0;
-    // The first optional string is the label (like NdT for translation notes)
-    // If only a number is in the label, it is ignored.
-    let mut footnotes: Vec<(Option<String>, String)> = vec![];
-    // let mut footnotes: Vec<String> = vec!();
+    let mut out = node_to_typst(&mut root, &mut state);
+
+    let ConversionState {
+        footnote_refs,
+        footnotes,
+        origins,
+        error,
+        ..
+    } = state;
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    // Footnotes are moved to their first reference, later references point to it
+    let mut resolved = Vec::new();
+    for (def_id, ref_label) in &footnote_refs {
+        if resolved.contains(def_id) {
+            continue;
+        }
+        resolved.push(*def_id);
+
+        let (label, content) = footnotes.get(def_id).context(UndefinedFootnoteSnafu {
+            label: ref_label.clone().unwrap_or_default(),
+        })?;
+        let content = match label {
+            // Label like NdT1 extract "NdT", only a number is not shown
+            Some(label) if label.parse::<u8>().is_err() => {
+                format!("#emph[{}:] {content}", sanitize_label(label))
+            }
+            _ => content.clone(),
+        };
+
+        let placeholder = footnote_placeholder(*def_id);
+        let count = footnote_refs.iter().filter(|(id, _)| id == def_id).count();
+        if count == 1 {
+            out = out.replace(&placeholder, &format!("#footnote[{content}]"));
+        } else {
+            let typst_label = footnote_label(*def_id, label.as_deref());
+            out = out.replacen(
+                &placeholder,
+                &format!("#footnote[{content}]<{typst_label}>"),
+                1,
+            );
+            out = out.replace(&placeholder, &format!("#footnote(<{typst_label}>)"));
+        }
+    }
+
+    Ok(take_markers(&out, &origins))
+}
+
+/// Stands for a footnote in the output until all the definitions are converted.
+///
+/// Private use characters can't clash with the markdown, even inside raw code.
+fn footnote_placeholder(def_id: usize) -> String {
+    format!("{FOOTNOTE_START}{def_id}{FOOTNOTE_END}")
+}
+
+/// Typst label of a footnote referenced several times.
+fn footnote_label(def_id: usize, label: Option<&str>) -> String {
+    match label {
+        Some(label) => {
+            let label: String = label
+                .chars()
+                .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            format!("footnote-{label}")
+        }
+        None => format!("footnote-{def_id}"),
+    }
+}
+
+/// Text of the first heading of a markdown document, used as title when there is no frontmatter.
+pub fn first_heading(markdown: &str) -> Option<String> {
+    let md = &mut MarkdownIt::new();
+    markdown_it::plugins::cmark::add(md);
+
+    let root = md.parse(markdown);
+    let mut heading = None;
+    root.walk(|node, _depth| {
+        if heading.is_none() && (node.is::<ATXHeading>() || node.is::<SetextHeading>()) {
+            heading = Some(node.collect_text().trim().to_string());
+        }
+    });
+    heading.filter(|heading| !heading.is_empty())
+}
+
+/// Insert a marker for the origin of a node, before the output it produced from `from`.
+///
+/// The marker goes after leading whitespace, which callers may trim.
+fn mark_origin(out: &mut String, from: usize, origin: Option<usize>, state: &mut ConversionState) {
+    let Some(origin) = origin else {
+        return;
+    };
+    let Some(content_start) = out[from..].find(|c: char| !c.is_whitespace()) else {
+        return;
+    };
+
+    out.insert_str(
+        from + content_start,
+        &format!("{MARKER_START}{}{MARKER_END}", state.origins.len()),
+    );
+    state.origins.push(origin);
+}
+
+/// Remove the markers from the output, and map their positions to their origins.
+fn take_markers(marked: &str, origins: &[usize]) -> (String, SpanMap) {
+    let mut out = String::with_capacity(marked.len());
+    let mut spans = SpanMap::default();
+
+    let mut rest = marked;
+    while let Some(start) = rest.find(MARKER_START) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + MARKER_START.len_utf8()..];
+
+        let end = rest.find(MARKER_END).unwrap_or(rest.len());
+        if let Some(origin) = rest[..end]
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| origins.get(i))
+        {
+            spans.push(out.len(), Origin::Markdown(*origin));
+        }
+        rest = &rest[(end + MARKER_END.len_utf8()).min(rest.len())..];
+    }
+    out.push_str(rest);
+
+    (out, spans)
+}
+
+/// Convert a node and all its descendants to Typst markup.
+fn node_to_typst(root: &mut Node, state: &mut ConversionState) -> String {
+    let mut out = String::new();
+    state.nesting += 1;
+
+    root.walk_mut(|node, depth| {
+        let from = out.len();
+        let origin = node
+            .srcmap
+            .as_ref()
+            .map(|srcmap| srcmap.get_byte_offsets().0);
 
-    root.walk_mut(|node, _| {
         if node.is::<ATXHeading>() {
-            let node: &ATXHeading = node.node_value.downcast_ref().unwrap();
-            out.push_str(&"=".repeat(node.level as usize));
+            let level = node.cast::<ATXHeading>().unwrap().level;
+            out.push_str("\n\n");
+            out.push_str(&"=".repeat(level as usize));
             out.push_str(" ");
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("\n");
         } else if node.is::<Strong>() {
-            out.push_str("#strong([");
-            // TODO: maybe parse those as markdown too?
-            out.push_str(&node.collect_text());
-            // IS THIS CORRECT? Replace collected children with emptiness
+            out.push_str("#strong[");
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("]");
+        } else if node.is::<Em>() {
+            out.push_str("#emph[");
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("]");
+        } else if node.is::<Strikethrough>() {
+            out.push_str("#strike[");
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("]");
+        } else if node.is::<Link>() {
+            let url = node.cast::<Link>().unwrap().url.clone();
+            out.push_str(&format!("#link({})[", typst_string(&url)));
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("]");
+            out.push_str(&print_link(&url, state.print_links));
+        } else if node.is::<Autolink>() {
+            // The URL is already the text of the link, no need to print it again
+            let url = node.cast::<Autolink>().unwrap().url.clone();
+            out.push_str(&format!("#link({})[", typst_string(&url)));
+            out.push_str(&children_to_typst(node, state));
+            out.push_str("]");
+        } else if node.is::<CodeInline>() {
+            // Raw text is never escaped
+            out.push_str(&format!("#raw({})", typst_string(&node.collect_text())));
             node.children = vec![];
-            out.push_str("])")
+        } else if node.is::<Blockquote>() {
+            // Themes can override the rendering of quotes by exporting a zine_quote function
+            out.push_str("\n\n#zine_quote");
+            if let Some(attribution) = take_attribution(node) {
+                let attribution: String = attribution
+                    .into_iter()
+                    .map(|mut child| node_to_typst(&mut child, state))
+                    .collect();
+                out.push_str(&format!("(attribution: [{}])", attribution.trim()));
+            }
+            out.push_str("[");
+            out.push_str(children_to_typst(node, state).trim());
+            out.push_str("]\n");
+        } else if node.is::<Table>() {
+            out.push_str(&table_to_typst(node, state));
+        } else if node.is::<CodeFence>() {
+            let fence = node.cast::<CodeFence>().unwrap();
+            // Only the first word of the info string is the language
+            let lang = fence.info.split_whitespace().next();
+            out.push_str(&typst_raw_block(&fence.content, lang));
+        } else if node.is::<CodeBlock>() {
+            let block = node.cast::<CodeBlock>().unwrap();
+            out.push_str(&typst_raw_block(&block.content, None));
         } else if node.is::<Text>() {
             let node: &Text = node.node_value.downcast_ref().unwrap();
             out.push_str(&typst_escape(&node.content));
-            out.push_str("\n");
         } else if node.is::<TextSpecial>() {
+            // Markdown escapes and entities, already decoded in content
             let node: &TextSpecial = node.node_value.downcast_ref().unwrap();
-            out.push_str(&node.markup);
+            out.push_str(&typst_escape(&node.content));
         } else if node.is::<Paragraph>() {
             out.push_str("\n");
             out.push_str("\n");
@@ -78,82 +332,339 @@ pub fn markdown_to_typst_content(markdown: &str) -> String {
             out.push_str("\n");
         } else if node.is::<TypstMacroNode>() {
             let node: &TypstMacroNode = node.node_value.downcast_ref().unwrap();
-            // Recurse parsing markdown inside the macro
-            out.push_str(&markdown_to_typst_content(&node.0));
+            out.push_str(&node.call);
+            out.push_str("[\n");
+            // Recurse parsing markdown inside the macro, which is not at block level anymore
+            let body_state = ConversionState {
+                print_links: state.print_links,
+                nesting: state.nesting,
+                ..Default::default()
+            };
+            match convert(&node.body, body_state) {
+                Ok((body, _spans)) => out.push_str(body.trim()),
+                Err(e) => {
+                    state.error.get_or_insert(e);
+                }
+            }
+            out.push_str("\n]\n");
         } else if node.is::<InlineFootnote>() {
-            footnote_counter += 1;
-            out.push_str(&format!("[^{}]", footnote_counter));
+            // Holds the definition and the reference of the inline footnote, converted below
         } else if node.is::<FootnoteReference>() {
-            footnote_counter += 1;
-            out.push_str(&format!("[^{}]", footnote_counter));
+            let reference = node.cast::<FootnoteReference>().unwrap();
+            out.push_str(&footnote_placeholder(reference.def_id));
+            state
+                .footnote_refs
+                .push((reference.def_id, reference.label.clone()));
         } else if node.is::<FootnoteDefinition>() {
             let typed_node: &FootnoteDefinition = node.node_value.downcast_ref().unwrap();
             let label = typed_node.label.to_owned();
+            let def_id = typed_node.def_id;
 
-            footnotes.push((label, format!("#footnote[{}]", &node.collect_text())));
-            node.children = vec![];
-        } else if node.is::<BulletList>() {
-            list_type = ListType::NotNumbered;
-        } else if node.is::<OrderedList>() {
-            list_type = ListType::Numbered;
-        } else if node.is::<ListItem>() {
-            out.push_str("\n");
-
-            match list_type {
-                ListType::NotNumbered => {
-                    out.push_str("- ");
-                }
-                ListType::Numbered => {
-                    out.push_str("+ ");
+            let content = children_to_typst(node, state);
+            match def_id {
+                Some(def_id) => {
+                    state
+                        .footnotes
+                        .insert(def_id, (label, content.trim().to_string()));
                 }
+                None => debug!("Dropping footnote {label:?}, which is never referenced"),
             }
-
-            out.push_str(&node.collect_text());
+        } else if node.is::<BulletList>() || node.is::<OrderedList>() {
+            out.push_str(&list_to_typst(node, state));
         } else if node.is::<Image>() {
-            // TODO: support caption
-            // TODO: support specifying image size
-            //  for exmaple with https://stackoverflow.com/questions/14675913/changing-image-size-in-markdown/21242579#21242579
-            //  or with title https://docs.rs/markdown-it/latest/markdown_it/generics/inline/full_link/index.html
-            let typed_node: &Image = node.node_value.downcast_ref().unwrap();
-            out.push_str(&format!("\n#image(height: 100%, \"{}\")\n", typed_node.url));
-
-            // Remove the image caption for the moment
-            node.children = vec!();
+            // Only the walk of the whole document reaches the images of its paragraphs, which
+            // are not inside another element
+            let block_level = state.nesting == 1 && depth <= 2;
+            out.push_str(&image_to_typst(node, block_level, state));
         } else {
             debug!("Unknown node type: {}", node.node_type.name);
         }
+
+        mark_origin(&mut out, from, origin, state);
     });
 
-    if footnote_counter != footnotes.len() {
-        panic!(
-            "Counted {} footnotes but found {} actual content for footnotes",
-            footnote_counter,
-            footnotes.len()
+    state.nesting -= 1;
+    out
+}
+
+/// Convert the children of a node to Typst markup, for example to wrap them in a function call.
+///
+/// The children are consumed so that the walk does not visit them a second time.
+fn children_to_typst(node: &mut Node, state: &mut ConversionState) -> String {
+    let mut out = String::new();
+
+    for mut child in std::mem::take(&mut node.children) {
+        out.push_str(&node_to_typst(&mut child, state));
+    }
+
+    out
+}
+
+/// Dashes introducing the attribution on the last line of a blockquote.
+const ATTRIBUTION_DASHES: [&str; 3] = ["—", "―", "--"];
+
+/// Remove the attribution line (like `— Author`) at the end of a blockquote, and return it
+/// without the dash.
+fn take_attribution(quote: &mut Node) -> Option<Vec<Node>> {
+    let paragraph = quote.children.last_mut().filter(|n| n.is::<Paragraph>())?;
+
+    // The last line starts after the last line break
+    let line_start = paragraph
+        .children
+        .iter()
+        .rposition(|n| n.is::<Softbreak>() || n.is::<Hardbreak>())
+        .map_or(0, |i| i + 1);
+
+    let text = paragraph.children.get_mut(line_start)?.cast_mut::<Text>()?;
+    let dash = ATTRIBUTION_DASHES
+        .iter()
+        .find(|dash| text.content.starts_with(*dash))?;
+    text.content = text.content[dash.len()..].trim_start().to_string();
+
+    let attribution = paragraph.children.split_off(line_start);
+    // Drop the line break before the attribution
+    paragraph.children.pop();
+
+    if paragraph.children.is_empty() {
+        quote.children.pop();
+    }
+
+    Some(attribution)
+}
+
+/// Convert a bullet or ordered list, including nested lists and formatting inside items.
+///
+/// Nested lists are converted recursively and indented inside their parent item. Ordered lists
+/// keep their start number.
+fn list_to_typst(list: &mut Node, state: &mut ConversionState) -> String {
+    let start = list.cast::<OrderedList>().map(|ordered| ordered.start);
+
+    // Paragraphs are only kept inside items of loose lists
+    let tight = !list
+        .children
+        .iter()
+        .any(|item| item.children.iter().any(|child| child.is::<Paragraph>()));
+
+    let mut items = Vec::new();
+
+    for (i, mut item) in std::mem::take(&mut list.children).into_iter().enumerate() {
+        let marker = match start {
+            None => "-".to_string(),
+            Some(start) if i == 0 && start != 1 => format!("{start}."),
+            Some(_) => "+".to_string(),
+        };
+
+        let mut content = String::new();
+        for mut child in std::mem::take(&mut item.children) {
+            if child.is::<BulletList>() || child.is::<OrderedList>() {
+                // A nested list starts on the next line, without a blank line that would make
+                // the item loose
+                content.push_str("\n");
+                content.push_str(list_to_typst(&mut child, state).trim());
+            } else {
+                content.push_str(&node_to_typst(&mut child, state));
+            }
+        }
+
+        items.push(format!("{marker} {}", indent_continuation(content.trim())));
+    }
+
+    let separator = if tight { "\n" } else { "\n\n" };
+    format!("\n\n{}\n", items.join(separator))
+}
+
+/// Indent every line but the first, so that it stays inside the current list item.
+fn indent_continuation(content: &str) -> String {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("  {line}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Layout of an image, from the title of the markdown image.
+///
+/// The title contains space-separated presets and `key=value` attributes, for example
+/// `![Caption](image.png "half placement=top")`:
+///
+/// - `full`: full-bleed image on its own page, without margins
+/// - `half`: half-page height
+/// - `inline`: small illustration inside the text, instead of a figure
+/// - `width=..`/`height=..`: a Typst length or ratio, like `8cm` or `50%`
+/// - `placement=..`: float the figure at the `top` or `bottom` of the page, or `auto`
+#[derive(Debug, Default, PartialEq)]
+struct ImageLayout {
+    width: Option<String>,
+    height: Option<String>,
+    placement: Option<String>,
+    full: bool,
+    inline: bool,
+}
+
+impl ImageLayout {
+    fn from_title(title: &str) -> Self {
+        let mut layout = Self::default();
+
+        for word in title.split_whitespace() {
+            match word.split_once('=') {
+                None if word == "full" => layout.full = true,
+                None if word == "half" => layout.height = Some("50%".to_string()),
+                None if word == "inline" => layout.inline = true,
+                Some(("width", value)) if is_typst_length(value) => {
+                    layout.width = Some(value.to_string())
+                }
+                Some(("height", value)) if is_typst_length(value) => {
+                    layout.height = Some(value.to_string())
+                }
+                Some(("placement", value)) if ["auto", "top", "bottom"].contains(&value) => {
+                    layout.placement = Some(value.to_string())
+                }
+                _ => warn!("Ignoring unknown image attribute {word} in title \"{title}\""),
+            }
+        }
+
+        layout
+    }
+
+    /// Size arguments for the Typst image function, with `default` when no size is given
+    fn size_args(&self, default: &str) -> String {
+        match (&self.width, &self.height) {
+            (None, None) => format!("{default}, "),
+            (Some(width), None) => format!("width: {width}, "),
+            (None, Some(height)) => format!("height: {height}, "),
+            (Some(width), Some(height)) => format!("width: {width}, height: {height}, "),
+        }
+    }
+}
+
+/// Convert an image to a figure, using the alt text as caption.
+///
+/// A full-bleed page can only be emitted at `block_level`, outside of any other element.
+/// Elsewhere, a full image becomes a full-width figure.
+fn image_to_typst(node: &mut Node, block_level: bool, state: &mut ConversionState) -> String {
+    let image = node.cast::<Image>().unwrap();
+    let url = typst_string(&image.url);
+    let mut layout = image
+        .title
+        .as_deref()
+        .map(ImageLayout::from_title)
+        .unwrap_or_default();
+    let alt = typst_string(&node.collect_text());
+    let caption = children_to_typst(node, state).trim().to_string();
+
+    if layout.full {
+        if block_level {
+            // No room for a caption on a full-bleed page
+            return format!(
+                "\n#page(margin: 0pt, header: none, footer: none)[#image(width: 100%, height: 100%, fit: \"cover\", {url}, alt: {alt})]\n"
+            );
+        }
+
+        debug!("Full-bleed image {url} is inside another element, using a full-width figure");
+        layout.width = Some("100%".to_string());
+        layout.height = None;
+    }
+
+    if layout.inline {
+        return format!(
+            "#box(image({}{url}, alt: {alt}))",
+            layout.size_args("height: 1em")
         );
     }
 
-    while footnote_counter > 0 {
-        let (label, content) = &footnotes[footnote_counter - 1];
-        if let Some(label) = label {
-            if let Ok(_n) = label.parse::<u8>() {
-                // Only a number, don't care about nothing
-                out = out.replace(&format!("[^{}]", footnote_counter), &content);
+    let is_figure = !caption.is_empty() || layout.placement.is_some() || layout.full;
+    if !is_figure {
+        return format!(
+            "\n#image({}{url}, alt: {alt})\n",
+            layout.size_args("height: 100%")
+        );
+    }
+
+    // The caption takes room too, so a figure fits the width instead of the page height
+    let mut out = format!(
+        "\n#figure(image({}{url}, alt: {alt})",
+        layout.size_args("width: 100%")
+    );
+    if !caption.is_empty() {
+        out.push_str(&format!(", caption: [{caption}]"));
+    }
+    if let Some(placement) = &layout.placement {
+        out.push_str(&format!(", placement: {placement}"));
+    }
+    out.push_str(")\n");
+    out
+}
+
+/// Convert a table, with column alignment from the `:---:` markers.
+///
+/// The header row is a `table.header`, so that themes can style it
+/// (for example with `show table.cell.where(y: 0)`).
+fn table_to_typst(table: &mut Node, state: &mut ConversionState) -> String {
+    let align: Vec<&str> = table
+        .cast::<Table>()
+        .unwrap()
+        .alignments
+        .iter()
+        .map(|alignment| match alignment {
+            ColumnAlignment::None => "auto",
+            ColumnAlignment::Left => "left",
+            ColumnAlignment::Center => "center",
+            ColumnAlignment::Right => "right",
+        })
+        .collect();
+
+    let mut out = String::from("\n\n#table(\n");
+    out.push_str(&format!("  columns: {},\n", align.len()));
+    out.push_str(&format!("  align: ({},),\n", align.join(", ")));
+
+    for mut section in std::mem::take(&mut table.children) {
+        let is_header = section.is::<TableHead>();
+
+        for mut row in std::mem::take(&mut section.children) {
+            let cells: Vec<String> = std::mem::take(&mut row.children)
+                .into_iter()
+                .map(|mut cell| format!("[{}]", children_to_typst(&mut cell, state).trim()))
+                .collect();
+
+            if is_header {
+                out.push_str(&format!("  table.header({}),\n", cells.join(", ")));
             } else {
-                // Label like NdT1 extract "NdT"
-                let new_label = sanitize_label(&label);
-                out = out.replace(
-                    &format!("[^{}]", footnote_counter),
-                    &content.replace("#footnote[", &format!("#footnote[#emph[{}:] ", new_label)), // &format!("{}: {}", new_label, &content),
-                );
+                out.push_str(&format!("  {},\n", cells.join(", ")));
             }
-        } else {
-            // No label, is that even possible?
-            // out = out.replace(&format!("[^{}]", footnote_counter), &content);
-            unreachable!();
         }
-        footnote_counter -= 1;
     }
 
+    out.push_str(")\n");
+    out
+}
+
+/// Print the URL after a link, for paper readers.
+fn print_link(url: &str, print_links: PrintLinks) -> String {
+    match print_links {
+        PrintLinks::None => String::new(),
+        PrintLinks::Footnote => format!("#footnote[#link({})]", typst_string(url)),
+        PrintLinks::Inline => format!(" (#link({}))", typst_string(url)),
+    }
+}
+
+/// Typst raw block for a code block, with syntax highlighting when the language is known.
+///
+/// The code is passed as a string literal so it's preserved exactly, without escaping.
+fn typst_raw_block(code: &str, lang: Option<&str>) -> String {
+    let mut out = String::from("\n\n#raw(block: true, ");
+    if let Some(lang) = lang {
+        out.push_str(&format!("lang: {}, ", typst_string(lang)));
+    }
+    // The last newline terminates the last line, it's not part of the code
+    out.push_str(&typst_string(code.strip_suffix('\n').unwrap_or(code)));
+    out.push_str(")\n");
     out
 }
 
@@ -162,26 +673,31 @@ pub fn markdown_to_typst_content(markdown: &str) -> String {
 // TODO: recursive macros
 
 #[derive(Clone, Debug)]
-struct TypstMacroNode(String);
+struct TypstMacroNode {
+    /// Function call with the macro arguments, before the body
+    call: String,
+    /// Markdown body of the macro
+    body: String,
+}
 
 impl TypstMacroNode {
     pub fn from_raw_macro(m: &BlockMacro) -> Self {
-        let mut out = String::from("\n\n#");
-        out.push_str(&m.name);
-        out.push_str("(\n");
+        let mut call = String::from("\n\n#");
+        call.push_str(&m.name);
+        call.push_str("(\n");
         for (k, v) in &m.args {
-            out.push_str("  ");
-            out.push_str(&k);
-            out.push_str(": ");
-            out.push_str(&v.to_typst());
-            out.push_str(",\n");
+            call.push_str("  ");
+            call.push_str(&k);
+            call.push_str(": ");
+            call.push_str(&v.to_typst());
+            call.push_str(",\n");
         }
-        out.push_str("  [\n");
-        out.push_str(&typst_escape(&m.body));
-        out.push_str("  ]\n");
-        out.push_str(")\n");
+        call.push_str(")");
 
-        Self(out)
+        Self {
+            call,
+            body: m.body.clone(),
+        }
     }
 
     // pub fn n_lines(&self) -> usize {
@@ -256,3 +772,258 @@ impl BlockRule for BlockMacroScanner {
 pub fn sanitize_label(label: &str) -> String {
     label.chars().filter(|c| !c.is_ascii_digit()).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_inline_formatting() {
+        assert_eq!(
+            markdown_to_typst_content("**bold _em_** and ~~gone~~", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#strong[bold #emph[em]] and #strike[gone]",
+        );
+    }
+
+    #[test]
+    fn code_fence() {
+        assert_eq!(
+            markdown_to_typst_content(
+                "```rust ignore\nlet a = *b; // @c [d]\n\n```\n",
+                PrintLinks::None
+            )
+            .unwrap()
+            .trim(),
+            "#raw(block: true, lang: \"rust\", \"let a = *b; // @c [d]\\n\")",
+        );
+    }
+
+    #[test]
+    fn links() {
+        let md = "[Zines](https://example.org) or <https://example.com>";
+        assert_eq!(
+            markdown_to_typst_content(md, PrintLinks::None).unwrap().trim(),
+            "#link(\"https://example.org\")[Zines] or #link(\"https://example.com\")[https:\\//example.com]",
+        );
+        assert_eq!(
+            markdown_to_typst_content(md, PrintLinks::Footnote).unwrap().trim(),
+            "#link(\"https://example.org\")[Zines]#footnote[#link(\"https://example.org\")] or #link(\"https://example.com\")[https:\\//example.com]",
+        );
+        assert_eq!(
+            markdown_to_typst_content(md, PrintLinks::Inline).unwrap().trim(),
+            "#link(\"https://example.org\")[Zines] (#link(\"https://example.org\")) or #link(\"https://example.com\")[https:\\//example.com]",
+        );
+    }
+
+    #[test]
+    fn blockquote() {
+        assert_eq!(
+            markdown_to_typst_content("> Be gay\n> do *crime*", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#zine_quote[Be gay\ndo #emph[crime]]",
+        );
+        assert_eq!(
+            markdown_to_typst_content("> Be gay\n> do crime\n> — Anonymous", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#zine_quote(attribution: [Anonymous])[Be gay\ndo crime]",
+        );
+        assert_eq!(
+            markdown_to_typst_content("> Be gay\n>\n> -- *Anonymous*", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#zine_quote(attribution: [#emph[Anonymous]])[Be gay]",
+        );
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(
+            markdown_to_typst_content(
+                "| Day | Price |\n|:--|:-:|\n| Monday | *2€* |\n| Tuesday | 3€ |",
+                PrintLinks::None
+            )
+            .unwrap()
+            .trim(),
+            "#table(
+  columns: 2,
+  align: (left, center,),
+  table.header([Day], [Price]),
+  [Monday], [#emph[2€]],
+  [Tuesday], [3€],
+)",
+        );
+    }
+
+    #[test]
+    fn nested_lists() {
+        assert_eq!(
+            markdown_to_typst_content(
+                "- a\n- **b**\n  1. c\n  2. d\n     - e\n- f",
+                PrintLinks::None
+            )
+            .unwrap()
+            .trim(),
+            "- a\n- #strong[b]\n  + c\n  + d\n    - e\n- f",
+        );
+    }
+
+    #[test]
+    fn ordered_list_start() {
+        assert_eq!(
+            markdown_to_typst_content("3. a\n4. b", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "3. a\n+ b",
+        );
+    }
+
+    #[test]
+    fn loose_list() {
+        assert_eq!(
+            markdown_to_typst_content("- a\n\n- b\n\n  more b", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "- a\n\n- b\n\n  more b",
+        );
+    }
+
+    #[test]
+    fn images() {
+        assert_eq!(
+            markdown_to_typst_content("![](cat.png)", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#image(height: 100%, \"cat.png\", alt: \"\")",
+        );
+        assert_eq!(
+            markdown_to_typst_content("![A cat](cat.png)", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#figure(image(width: 100%, \"cat.png\", alt: \"A cat\"), caption: [A cat])",
+        );
+        assert_eq!(
+            markdown_to_typst_content(
+                "![A *cat*](cat.png \"half placement=top\")",
+                PrintLinks::None
+            ).unwrap()
+            .trim(),
+            "#figure(image(height: 50%, \"cat.png\", alt: \"A cat\"), caption: [A #emph[cat]], placement: top)",
+        );
+        assert_eq!(
+            markdown_to_typst_content("A ![cat](cat.png \"inline\") cat", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "A #box(image(height: 1em, \"cat.png\", alt: \"cat\")) cat",
+        );
+    }
+
+    #[test]
+    fn full_images() {
+        assert!(
+            markdown_to_typst_content("![](cat.png \"full\")", PrintLinks::None)
+                .unwrap()
+                .trim()
+                .starts_with("#page(")
+        );
+        assert_eq!(
+            markdown_to_typst_content("- ![](cat.png \"full\")", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "- #figure(image(width: 100%, \"cat.png\", alt: \"\"))",
+        );
+        assert_eq!(
+            markdown_to_typst_content("> ![](cat.png \"full\")", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#zine_quote[#figure(image(width: 100%, \"cat.png\", alt: \"\"))]",
+        );
+    }
+
+    #[test]
+    fn image_layout() {
+        assert_eq!(
+            ImageLayout::from_title("full width=8cm height=lots placement=left"),
+            ImageLayout {
+                width: Some("8cm".to_string()),
+                full: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn escaped_text() {
+        assert_eq!(
+            markdown_to_typst_content("\\- 50% off \\*today\\* &amp; #1 [sic]", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "\\- 50% off \\*today\\* & \\#1 \\[sic\\]",
+        );
+    }
+
+    #[test]
+    fn inline_code_is_raw() {
+        assert_eq!(
+            markdown_to_typst_content("Run `echo \"*@\\\"`", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "Run #raw(\"echo \\\"*@\\\\\\\"\")",
+        );
+    }
+    #[test]
+    fn footnotes() {
+        assert_eq!(
+            markdown_to_typst_content(
+                "a[^1] b[^NdT]\n\n[^1]: One\n\n[^NdT]: Two",
+                PrintLinks::None
+            )
+            .unwrap()
+            .trim(),
+            "a#footnote[One] b#footnote[#emph[NdT:] Two]",
+        );
+        // Later references point to the footnote of the first one
+        assert_eq!(
+            markdown_to_typst_content("a[^1] b[^1]\n\n[^1]: Note", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "a#footnote[Note]<footnote-1> b#footnote(<footnote-1>)",
+        );
+        assert_eq!(
+            markdown_to_typst_content("a^[Inline *note*] b", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "a#footnote[Inline #emph[note]] b",
+        );
+        // Raw code looking like a reference is left alone
+        assert_eq!(
+            markdown_to_typst_content("`[^0]` a[^1]\n\n[^1]: Note", PrintLinks::None)
+                .unwrap()
+                .trim(),
+            "#raw(\"[^0]\") a#footnote[Note]",
+        );
+    }
+
+    #[test]
+    fn span_map() {
+        let md = "Hello\n\n- a *b*\n  - c\n\nSee[^1]\n\n[^1]: Note";
+        let (typst, spans) = markdown_to_typst_content_mapped(md, PrintLinks::None).unwrap();
+        assert!(!typst.contains(MARKER_START));
+
+        let origin = |typst_text: &str| spans.lookup(typst.find(typst_text).unwrap());
+        assert_eq!(origin("Hello"), Some(&Origin::Markdown(0)));
+        assert_eq!(
+            origin("#emph"),
+            Some(&Origin::Markdown(md.find('*').unwrap()))
+        );
+        assert_eq!(origin("c"), Some(&Origin::Markdown(md.find('c').unwrap())));
+        // The footnote is moved to its reference, but still points to its definition
+        assert_eq!(
+            origin("Note"),
+            Some(&Origin::Markdown(md.find("Note").unwrap()))
+        );
+    }
+}
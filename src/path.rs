@@ -1,5 +1,6 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use derive_more::{AsRef, Deref, Display, From, Into};
+use snafu::prelude::*;
 
 use crate::{error::*, theme::Theme, zine::ZineFile};
 
@@ -12,6 +13,15 @@ use crate::{error::*, theme::Theme, zine::ZineFile};
 pub struct BaseDir(Utf8PathBuf);
 
 impl BaseDir {
+    /// Find a [`BaseDir`] from a given path, which may be the basedir itself or any path inside.
+    pub fn from_path(path: &Utf8Path) -> Result<Self, Error> {
+        if Self::is_basedir(path) {
+            return Ok(BaseDir(path.to_path_buf()));
+        }
+
+        Self::from_child(path)
+    }
+
     /// Find a parent [`BaseDir`] from a given path.
     pub fn from_child(orig_path: &Utf8Path) -> Result<Self, Error> {
         let mut path = orig_path;
@@ -19,7 +29,7 @@ impl BaseDir {
         while let Some(parent) = path.parent() {
             path = parent;
             trace!("Investigating {path} as basedir...");
-            if !path.join("content").is_dir() || !path.join("themes").is_dir() {
+            if !Self::is_basedir(path) {
                 continue;
             }
 
@@ -31,6 +41,104 @@ impl BaseDir {
         });
     }
 
+    fn is_basedir(path: &Utf8Path) -> bool {
+        path.join("content").is_dir() && path.join("themes").is_dir()
+    }
+
+    /// Find all the zines in the `content` folder.
+    ///
+    /// Every `.md` file is a zine. `.typ` files are zines too, except the ones generated from a
+    /// sibling markdown zine (`foo.<theme>.typ` next to `foo.md`).
+    pub fn zines(&self) -> Result<Vec<RootPath>, Error> {
+        let mut zines = Vec::new();
+        self.find_zines(&self.join("content").absolute(), &mut zines)?;
+        zines.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(zines)
+    }
+
+    fn find_zines(&self, dir: &Utf8Path, zines: &mut Vec<RootPath>) -> Result<(), Error> {
+        let entries = dir.read_dir_utf8().context(ReadDirSnafu {
+            path: dir.to_path_buf(),
+        })?;
+
+        for entry in entries {
+            let entry = entry.context(ReadDirSnafu {
+                path: dir.to_path_buf(),
+            })?;
+            let path = entry.path();
+
+            if entry.file_name().starts_with(".") {
+                continue;
+            }
+
+            if path.is_dir() {
+                self.find_zines(path, zines)?;
+                continue;
+            }
+
+            let is_zine = match path.extension() {
+                Some("md") => true,
+                Some("typ") => {
+                    // foo.theme.typ next to foo.md was generated with --write-typ
+                    let stem = Utf8Path::new(path.file_stem().unwrap_or_default());
+                    let generated = stem.extension().is_some()
+                        && stem.file_stem().is_some_and(|md_stem| {
+                            path.with_file_name(format!("{md_stem}.md")).is_file()
+                        });
+                    !generated
+                }
+                _ => false,
+            };
+
+            if is_zine {
+                zines.push(self.join(path));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find the names of all the themes, which are the folders in `themes`.
+    pub fn themes(&self) -> Result<Vec<String>, Error> {
+        let dir = self.join("themes").absolute();
+        let entries = dir
+            .read_dir_utf8()
+            .context(ReadDirSnafu { path: dir.clone() })?;
+
+        let mut themes = Vec::new();
+        for entry in entries {
+            let entry = entry.context(ReadDirSnafu { path: dir.clone() })?;
+
+            if entry.file_name().starts_with(".") || !entry.path().is_dir() {
+                continue;
+            }
+
+            themes.push(entry.file_name().to_string());
+        }
+
+        themes.sort();
+        Ok(themes)
+    }
+
+    /// The theme used by zines which don't choose one: the theme named `default`, or else the
+    /// only theme.
+    ///
+    /// This is the last resort for the whole basedir. A folder of zines picks its own default
+    /// theme with `themes` in its `_defaults.toml` (see [`load_frontmatter`]).
+    ///
+    /// [`load_frontmatter`]: crate::frontmatter::load_frontmatter
+    pub fn default_theme(&self) -> Result<Option<String>, Error> {
+        let mut themes = self.themes()?;
+        if themes.iter().any(|theme| theme == "default") {
+            return Ok(Some("default".to_string()));
+        }
+
+        Ok(match themes.len() {
+            1 => themes.pop(),
+            _ => None,
+        })
+    }
+
     /// Append a path to a [`BaseDir`].
     pub fn join(&self, path: impl AsRef<Utf8Path>) -> RootPath {
         let path = path.as_ref();
@@ -71,6 +179,23 @@ impl RootPath {
         self.root.0.join(&self.path)
     }
 
+    /// Where to write a build artifact derived from this path, with a new extension.
+    ///
+    /// Without an output directory, the artifact is written next to the source. Otherwise, the
+    /// structure of the `content` folder is mirrored inside the output directory. A relative
+    /// output directory is relative to the [`BaseDir`].
+    pub fn output(&self, out_dir: Option<&Utf8Path>, extension: &str) -> Utf8PathBuf {
+        let mut out = match out_dir {
+            Some(out_dir) => {
+                let relative = self.path.strip_prefix("content").unwrap_or(&self.path);
+                self.root.0.join(out_dir).join(relative)
+            }
+            None => self.absolute(),
+        };
+        out.set_extension(extension);
+        out
+    }
+
     pub fn relative_to_theme(&self, theme: &Theme) -> Utf8PathBuf {
         self.relative_to(&theme.relative_file().path)
     }
@@ -140,6 +265,24 @@ impl RootPath {
     }
 }
 
+/// A basedir in a temporary folder with `files` (path and content), deleted with the folder.
+#[cfg(test)]
+pub(crate) fn temp_basedir(files: &[(&str, &str)]) -> (tempfile::TempDir, BaseDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(dir.path()).unwrap();
+
+    std::fs::create_dir_all(root.join("content")).unwrap();
+    std::fs::create_dir_all(root.join("themes")).unwrap();
+    for (path, content) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    let basedir = BaseDir::from(root.to_path_buf());
+    (dir, basedir)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,6 +366,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn output() {
+        let path = RootPath {
+            root: BaseDir::from(Utf8PathBuf::from("/root")),
+            path: "content/a/b.fr.typ".into(),
+        };
+
+        assert_eq!(
+            path.output(None, "pdf"),
+            Utf8PathBuf::from("/root/content/a/b.fr.pdf"),
+        );
+        assert_eq!(
+            path.output(Some("public".into()), "pdf"),
+            Utf8PathBuf::from("/root/public/a/b.fr.pdf"),
+        );
+        assert_eq!(
+            path.output(Some("/tmp/out".into()), "1.png"),
+            Utf8PathBuf::from("/tmp/out/a/b.fr.1.png"),
+        );
+    }
+
     #[test]
     fn to() {
         let path = RootPath::new("/root".into(), "content/a/b.md".into());
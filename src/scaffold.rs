@@ -0,0 +1,197 @@
+use camino::Utf8Path;
+use snafu::prelude::*;
+
+use crate::{
+    error::*,
+    path::{BaseDir, RootPath},
+    theme::Theme,
+};
+
+/// Files of the starter theme created by [`init`], relative to its folder.
+const STARTER_THEME: &[(&str, &str)] = &[
+    ("theme.typ", include_str!("starter/theme.typ")),
+    ("theme.toml", include_str!("starter/theme.toml")),
+];
+
+/// Create a [`BaseDir`] at `path`, with an empty `content` folder and a starter theme.
+///
+/// Existing files are kept, so this can be run again to complete a basedir.
+pub fn init(path: &Utf8Path) -> Result<BaseDir, Error> {
+    create_dir(&path.join("content"))?;
+
+    let starter = path.join("themes/starter");
+    create_dir(&starter)?;
+    for (name, content) in STARTER_THEME {
+        let file = starter.join(name);
+        if file.exists() {
+            info!("Keeping existing {file}");
+            continue;
+        }
+
+        std::fs::write(&file, content).context(ScaffoldSnafu { path: file.clone() })?;
+        info!("Created {file}");
+    }
+
+    let path = path.canonicalize_utf8().context(ScaffoldSnafu {
+        path: path.to_path_buf(),
+    })?;
+    BaseDir::from_path(&path)
+}
+
+/// Create a new markdown zine at `content/<slug>/<slug>.md`.
+///
+/// Without a theme name, the only theme of the basedir is used. The frontmatter lists the
+/// settings of the theme: required ones are filled with placeholders, optional ones are
+/// commented out with their default.
+pub fn new_zine(basedir: &BaseDir, slug: &str, theme: Option<&str>) -> Result<RootPath, Error> {
+    ensure!(
+        !slug.is_empty()
+            && !slug.starts_with('.')
+            && !slug.contains(|c: char| c == '/' || c == '\\'),
+        InvalidSlugSnafu { slug }
+    );
+
+    let theme_name = match theme {
+        Some(theme) => theme.to_string(),
+        None => {
+            let themes = basedir.themes()?;
+            ensure!(
+                themes.len() == 1,
+                ChooseThemeSnafu {
+                    themes: themes.join(", ")
+                }
+            );
+            themes[0].clone()
+        }
+    };
+    ensure!(
+        basedir.themes()?.contains(&theme_name),
+        UnknownThemeSnafu { name: &theme_name }
+    );
+    let theme = Theme::load(basedir, &theme_name)?;
+
+    let zine = basedir.join(format!("content/{slug}/{slug}.md"));
+    let file = zine.absolute();
+    ensure!(!file.exists(), ZineExistsSnafu { path: file.clone() });
+
+    if let Some(parent) = file.parent() {
+        create_dir(parent)?;
+    }
+    std::fs::write(&file, zine_template(slug, &theme))
+        .context(ScaffoldSnafu { path: file.clone() })?;
+    info!("Created {file}");
+
+    Ok(zine)
+}
+
+/// The markdown of a new zine, with a frontmatter for the theme.
+fn zine_template(slug: &str, theme: &Theme) -> String {
+    let title = title_from_slug(slug);
+
+    let mut out = String::from("+++\n");
+    out.push_str(&format!("title = {}\n", toml::Value::from(title.as_str())));
+    out.push_str("# subtitle = \"\"\n# author = \"\"\n# description = \"\"\n# summary = \"\"\n");
+    out.push_str(&format!(
+        "\n[themes.{}]\n",
+        toml::Value::from(theme.name.as_str())
+    ));
+
+    if let Some(manifest) = &theme.manifest {
+        for (key, param) in &manifest.params {
+            let line = match (&param.default, param.kind.placeholder()) {
+                (Some(default), _) if !param.required => format!("# {key} = {default}"),
+                (_, Some(placeholder)) if param.required => format!("{key} = {placeholder}"),
+                // Left commented out, so that a missing required setting is reported
+                _ => format!("# {key} = ...  # required {}", param.kind),
+            };
+            out.push_str(&line);
+
+            if let Some(description) = &param.description {
+                out.push_str(&format!("  # {description}"));
+            }
+            out.push('\n');
+        }
+    }
+
+    out.push_str("+++\n\n");
+    out.push_str(&format!("# {title}\n\nWrite your zine here.\n"));
+    out
+}
+
+/// A title for a slug, like `My zine` for `my-zine`.
+pub(crate) fn title_from_slug(slug: &str) -> String {
+    let words = slug.replace(['-', '_'], " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
+
+fn create_dir(path: &Utf8Path) -> Result<(), Error> {
+    std::fs::create_dir_all(path).context(ScaffoldSnafu {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title() {
+        assert_eq!(title_from_slug("my-first_zine"), "My first zine");
+        assert_eq!(title_from_slug("été"), "Été");
+    }
+
+    #[test]
+    fn template() {
+        let mut theme = Theme::new(
+            &BaseDir::from(Utf8Path::new("/root").to_path_buf()),
+            "footheme",
+        );
+        theme.manifest = Some(
+            toml::from_str(
+                r##"
+                name = "footheme"
+
+                [params.cover_color]
+                type = "color"
+                default = "#ff0000"
+
+                [params.columns]
+                type = "int"
+                required = true
+                description = "Number of columns"
+
+                [params.logo]
+                type = "path"
+                required = true
+                "##,
+            )
+            .unwrap(),
+        );
+
+        let template = zine_template("my-zine", &theme);
+        let (_, frontmatter) = template.split_once("[themes.\"footheme\"]\n").unwrap();
+        assert_eq!(
+            frontmatter,
+            "columns = 1  # Number of columns\n\
+             # cover_color = \"#ff0000\"\n\
+             # logo = ...  # required path\n\
+             +++\n\n\
+             # My zine\n\n\
+             Write your zine here.\n"
+        );
+
+        let settings: toml::Table = toml::from_str(
+            template
+                .trim_start_matches("+++")
+                .split_once("\n+++")
+                .unwrap()
+                .0,
+        )
+        .unwrap();
+        assert!(settings["themes"]["footheme"]["columns"].is_integer());
+    }
+}
@@ -0,0 +1,276 @@
+use camino::Utf8PathBuf;
+
+use std::collections::HashMap;
+
+use crate::{
+    diag::{Location, Position},
+    path::RootPath,
+};
+
+/// Where a part of the Typst generated from a markdown zine comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    /// Byte offset in the markdown file (including its frontmatter)
+    Markdown(usize),
+    /// Setting from the frontmatter, like `title` or `themes.foo.cover_color`
+    FrontMatter(String),
+    /// Generated by zinifier without a counterpart in the markdown, like the theme import
+    Generated,
+}
+
+/// Map from byte offsets in generated Typst to their [`Origin`].
+///
+/// Every anchor applies from its offset until the next anchor.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpanMap {
+    anchors: Vec<(usize, Origin)>,
+}
+
+impl SpanMap {
+    /// Add an anchor, after all the existing ones.
+    pub fn push(&mut self, offset: usize, origin: Origin) {
+        debug_assert!(self
+            .anchors
+            .last()
+            .map_or(true, |(last, _)| *last <= offset));
+        self.anchors.push((offset, origin));
+    }
+
+    /// Append the anchors of a map for Typst inserted at `offset`, with markdown offsets moved
+    /// by `markdown_offset`.
+    pub fn append(&mut self, other: SpanMap, offset: usize, markdown_offset: usize) {
+        for (anchor, origin) in other.anchors {
+            let origin = match origin {
+                Origin::Markdown(md) => Origin::Markdown(md + markdown_offset),
+                origin => origin,
+            };
+            self.push(anchor + offset, origin);
+        }
+    }
+
+    /// The origin of a byte offset in the generated Typst.
+    pub fn lookup(&self, offset: usize) -> Option<&Origin> {
+        let index = self
+            .anchors
+            .partition_point(|(anchor, _)| *anchor <= offset);
+        self.anchors[..index].last().map(|(_, origin)| origin)
+    }
+}
+
+/// A markdown zine, to report diagnostics from its generated Typst against it.
+#[derive(Clone, Debug)]
+pub struct MarkdownSource {
+    /// Path to the markdown file, relative to the basedir
+    pub path: Utf8PathBuf,
+    /// Content of the markdown file, including its frontmatter
+    pub text: String,
+    /// Origins of the generated Typst
+    pub spans: SpanMap,
+    /// The `_defaults.toml` of the frontmatter keys the zine inherits
+    pub origins: HashMap<String, RootPath>,
+}
+
+impl MarkdownSource {
+    /// The location of an offset in the generated Typst, in the markdown file.
+    ///
+    /// Returns `None` for generated Typst, which has no location in the markdown.
+    pub fn locate(&self, typst_offset: usize) -> Option<Location> {
+        let (offset, frontmatter_key) = match self.spans.lookup(typst_offset)? {
+            Origin::Markdown(offset) => (Some(*offset), None),
+            Origin::FrontMatter(key) => match self.origins.get(key) {
+                Some(defaults) => return Some(frontmatter_location(defaults, key)),
+                None => (frontmatter_key_offset(&self.text, key), Some(key)),
+            },
+            Origin::Generated => return None,
+        };
+
+        Some(Location {
+            file: self.path.clone(),
+            range: offset.map(|offset| {
+                let (line, column) = line_column(&self.text, offset);
+                Position { line, column }.to_range()
+            }),
+            frontmatter_key: frontmatter_key.cloned(),
+        })
+    }
+}
+
+/// The location of a frontmatter key in a zine or a `_defaults.toml` file, without a range if
+/// it's not found.
+pub fn frontmatter_location(file: &RootPath, key: &str) -> Location {
+    let range = std::fs::read_to_string(file.absolute())
+        .ok()
+        .and_then(|text| {
+            let (line, column) = line_column(&text, frontmatter_key_offset(&text, key)?);
+            Some(Position { line, column }.to_range())
+        });
+
+    Location {
+        file: file.path.clone(),
+        range,
+        frontmatter_key: Some(key.to_string()),
+    }
+}
+
+/// 1-indexed line and column of a byte offset in a text.
+pub fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = match before.rfind('\n') {
+        Some(newline) => before[newline + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+    (line, column)
+}
+
+/// Byte offset of the line setting a frontmatter key, like `themes.foo.cover_color`.
+fn frontmatter_key_offset(text: &str, key: &str) -> Option<usize> {
+    if text.starts_with("---") {
+        yaml_key_offset(text, key)
+    } else {
+        toml_key_offset(text, key)
+    }
+}
+
+/// The key is looked for after the header of its table (`[themes.foo]`), or before any table
+/// for top-level keys.
+fn toml_key_offset(text: &str, key: &str) -> Option<usize> {
+    let (table, key) = match key.rsplit_once('.') {
+        Some((table, key)) => (Some(table), key),
+        None => (None, key),
+    };
+
+    let mut in_table = table.is_none();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+
+        if offset > 0 && trimmed == "+++" {
+            // End of the frontmatter
+            return None;
+        }
+
+        if trimmed.starts_with('[') {
+            let header = trimmed
+                .trim_matches(|c| c == '[' || c == ']')
+                .replace('"', "");
+            in_table = table == Some(header.as_str());
+        } else if in_table && is_key_line(trimmed, key, '=') {
+            return Some(offset + line.len() - line.trim_start().len());
+        }
+
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Each part of the key is looked for in the block of the previous one, which is more indented.
+fn yaml_key_offset(text: &str, key: &str) -> Option<usize> {
+    let mut parts = key.split('.').peekable();
+    let mut part = parts.next()?;
+    // Indentation of the block holding the current part, none at the top level
+    let mut block_indent = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+
+        if offset > 0 && trimmed == "---" {
+            // End of the frontmatter
+            return None;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        if offset > 0 && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            if block_indent.is_some_and(|block| indent <= block) {
+                // The block of the parent key is over
+                return None;
+            }
+
+            if is_key_line(trimmed, part, ':') {
+                if parts.peek().is_none() {
+                    return Some(offset + indent);
+                }
+                part = parts.next()?;
+                block_indent = Some(indent);
+            }
+        }
+
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Whether a trimmed line sets `key`, possibly quoted, with `separator` after it.
+fn is_key_line(line: &str, key: &str, separator: char) -> bool {
+    let unquoted = line.trim_start_matches(['"', '\'']);
+    unquoted
+        .strip_prefix(key)
+        .map(|rest| rest.trim_start_matches(['"', '\'']))
+        .is_some_and(|rest| rest.trim_start().starts_with(separator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let mut map = SpanMap::default();
+        map.push(0, Origin::Generated);
+        map.push(10, Origin::Markdown(3));
+        map.push(20, Origin::Markdown(7));
+
+        assert_eq!(map.lookup(5), Some(&Origin::Generated));
+        assert_eq!(map.lookup(10), Some(&Origin::Markdown(3)));
+        assert_eq!(map.lookup(19), Some(&Origin::Markdown(3)));
+        assert_eq!(map.lookup(200), Some(&Origin::Markdown(7)));
+        assert_eq!(SpanMap::default().lookup(5), None);
+    }
+
+    #[test]
+    fn frontmatter_keys() {
+        let text = "+++\ntitle = \"Zine\"\n\n[themes.foo]\n  title = 2\n+++\ntitle = 3\n";
+        let source = MarkdownSource {
+            path: "content/a/a.md".into(),
+            text: text.to_string(),
+            spans: SpanMap {
+                anchors: vec![
+                    (0, Origin::FrontMatter("title".to_string())),
+                    (10, Origin::FrontMatter("themes.foo.title".to_string())),
+                    (20, Origin::FrontMatter("themes.foo.missing".to_string())),
+                    (30, Origin::Markdown(text.find("title = 3").unwrap())),
+                ],
+            },
+            origins: HashMap::new(),
+        };
+
+        assert_eq!(
+            source.locate(0).unwrap().to_string(),
+            "content/a/a.md:2:1 (frontmatter title)"
+        );
+        assert_eq!(
+            source.locate(10).unwrap().to_string(),
+            "content/a/a.md:5:3 (frontmatter themes.foo.title)"
+        );
+        assert_eq!(
+            source.locate(20).unwrap().to_string(),
+            "content/a/a.md (frontmatter themes.foo.missing)"
+        );
+        assert_eq!(source.locate(30).unwrap().to_string(), "content/a/a.md:7:1");
+    }
+
+    #[test]
+    fn yaml_frontmatter_keys() {
+        let text =
+            "---\ntitle: Zine\nthemes:\n  foo:\n    # A comment\n    title: 2\n  bar: {}\n---\n";
+        assert_eq!(frontmatter_key_offset(text, "title"), text.find("title"));
+        assert_eq!(
+            frontmatter_key_offset(text, "themes.foo.title"),
+            text.find("title: 2")
+        );
+        assert_eq!(frontmatter_key_offset(text, "themes.bar.title"), None);
+        assert_eq!(frontmatter_key_offset(text, "themes.foo.missing"), None);
+    }
+}
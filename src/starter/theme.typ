@@ -0,0 +1,47 @@
+// Starter theme created by `zinifier init`, make it your own!
+//
+// Zinifier calls `zine` with the frontmatter of the zine, and the settings from its
+// `[themes.starter]` table. Settings are declared in theme.toml.
+
+#let zine(
+  title: none,
+  subtitle: none,
+  author: none,
+  description: none,
+  summary: none,
+  accent_color: rgb("#c0392b"),
+  paper: "a5",
+  body,
+) = {
+  set document(title: title, author: if author == none { () } else { author })
+  set page(paper: paper, margin: 1.5cm, numbering: "1")
+  set text(size: 10pt)
+  set par(justify: true)
+  show heading: set text(fill: accent_color)
+  show link: set text(fill: accent_color)
+
+  // Cover
+  page(numbering: none, fill: accent_color, {
+    set text(fill: white)
+    v(1fr)
+    text(size: 28pt, weight: "bold", title)
+    if subtitle != none {
+      linebreak()
+      text(size: 16pt, subtitle)
+    }
+    if author != none {
+      v(1em)
+      author
+    }
+    v(1fr)
+    if description != none {
+      description
+    }
+  })
+
+  if summary != none {
+    block(inset: 1em, stroke: accent_color, summary)
+  }
+
+  body
+}
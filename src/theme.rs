@@ -1,26 +1,460 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use snafu::prelude::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use typst::{
+    syntax::{FileId, Source, VirtualPath},
+    World,
+};
+use typst_library::layout::PagedDocument;
 
 use crate::{
+    diag::diagnostics,
+    error::*,
     path::{BaseDir, RootPath},
+    typ::{is_hex_color, is_typst_length, typst_string},
+    world::ZineWorld,
     zine::ZineFile,
 };
 
 #[derive(Clone, Debug)]
 pub struct Theme {
     pub basedir: BaseDir,
+    /// The `theme.typ` in the theme folder, which may come from a parent (see [`Theme::resolve`])
     pub themefile: RootPath,
     pub name: String,
+    /// The `theme.toml` in the theme folder if any, with params inherited from the parents
+    pub manifest: Option<ThemeManifest>,
+    /// The theme declared as parent in the manifest, if any
+    pub parent: Option<Box<Theme>>,
+}
+
+/// A `theme.toml` manifest, describing a theme and the settings it accepts.
+///
+/// ```toml
+/// name = "communesbrochures"
+/// version = "1.2.0"
+/// description = "A5 brochures with a colored cover"
+/// fonts = ["fonts/"]
+/// # Files missing from this theme are taken from the parent, and its params are inherited
+/// parent = "brochures"
+///
+/// [params.cover_color]
+/// type = "color"
+/// default = "#ff0000"
+///
+/// [params.logo]
+/// type = "path"
+/// required = true
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThemeManifest {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Settings accepted by the theme's `zine` function, by name
+    #[serde(default)]
+    pub params: BTreeMap<String, ThemeParam>,
+    /// Font files or folders bundled with the theme, relative to the theme folder
+    #[serde(default)]
+    pub fonts: Vec<Utf8PathBuf>,
+    pub parent: Option<String>,
+}
+
+/// A setting declared in a [`ThemeManifest`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThemeParam {
+    #[serde(rename = "type")]
+    pub kind: ParamKind,
+    /// Zines must set required settings, other settings may have a default
+    #[serde(default)]
+    pub required: bool,
+    pub default: Option<toml::Value>,
+    pub description: Option<String>,
+}
+
+impl ThemeParam {
+    /// Check the value of this param, set in the frontmatter or as default.
+    pub fn check(&self, key: &str, value: &toml::Value) -> Option<SettingProblem> {
+        (!self.kind.accepts(value)).then(|| SettingProblem::WrongType {
+            key: key.to_string(),
+            expected: self.kind,
+        })
+    }
+}
+
+/// The type of a [`ThemeParam`], which decides how it's passed to Typst.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamKind {
+    Bool,
+    Int,
+    /// A float, integers are accepted too
+    Float,
+    /// A string literal
+    String,
+    /// Text displayed as is in the zine, escaped as markup
+    Content,
+    /// A length or ratio, like `12pt` or `50%`
+    Length,
+    /// A hex color code, like `#ff0000`
+    Color,
+    /// A file next to the zine (or in the theme folder for defaults)
+    Path,
+    Array,
+    Dict,
+    /// Any TOML value
+    Any,
+}
+
+impl ParamKind {
+    /// Whether a frontmatter value has this type.
+    pub fn accepts(&self, value: &toml::Value) -> bool {
+        use toml::Value;
+
+        match (self, value) {
+            (Self::Any, _) => true,
+            (Self::Bool, Value::Boolean(_)) => true,
+            (Self::Int, Value::Integer(_)) => true,
+            (Self::Float, Value::Float(_) | Value::Integer(_)) => true,
+            (Self::String | Self::Content | Self::Path, Value::String(_)) => true,
+            (Self::Length, Value::String(s)) => is_typst_length(s),
+            (Self::Color, Value::String(s)) => is_hex_color(s),
+            (Self::Array, Value::Array(_)) => true,
+            (Self::Dict, Value::Table(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl ParamKind {
+    /// A value of this type to fill a required setting with, for example in a template.
+    ///
+    /// There's no sensible placeholder for a path or for any value.
+    pub fn placeholder(&self) -> Option<toml::Value> {
+        use toml::Value;
+
+        match self {
+            Self::Bool => Some(Value::Boolean(false)),
+            Self::Int => Some(Value::Integer(1)),
+            Self::Float => Some(Value::Float(1.0)),
+            Self::String | Self::Content => Some(Value::String("Lorem ipsum".to_string())),
+            Self::Length => Some(Value::String("1em".to_string())),
+            Self::Color => Some(Value::String("#808080".to_string())),
+            Self::Array => Some(Value::Array(Vec::new())),
+            Self::Dict => Some(Value::Table(toml::Table::new())),
+            Self::Path | Self::Any => None,
+        }
+    }
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Bool => "bool",
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::String => "string",
+            Self::Content => "content",
+            Self::Length => "length (like 12pt)",
+            Self::Color => "color (like #ff0000)",
+            Self::Path => "path",
+            Self::Array => "array",
+            Self::Dict => "dict",
+            Self::Any => "any value",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A frontmatter setting which doesn't match the [`ThemeManifest`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingProblem {
+    Unknown(String),
+    Missing(String),
+    WrongType { key: String, expected: ParamKind },
+}
+
+impl SettingProblem {
+    /// The setting this problem is about.
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Unknown(key) | Self::Missing(key) | Self::WrongType { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for SettingProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(key) => write!(f, "unknown setting `{key}`"),
+            Self::Missing(key) => write!(f, "missing required setting `{key}`"),
+            Self::WrongType { key, expected } => {
+                write!(f, "setting `{key}` should be a {expected}")
+            }
+        }
+    }
+}
+
+impl ThemeManifest {
+    /// Check frontmatter settings against the declared params.
+    pub fn check(&self, settings: &toml::Table) -> Vec<SettingProblem> {
+        let mut problems = Vec::new();
+
+        for (key, value) in settings {
+            match self.params.get(key) {
+                None => problems.push(SettingProblem::Unknown(key.clone())),
+                Some(param) => problems.extend(param.check(key, value)),
+            }
+        }
+
+        for (key, param) in &self.params {
+            if param.required && !settings.contains_key(key) {
+                problems.push(SettingProblem::Missing(key.clone()));
+            }
+        }
+
+        problems
+    }
+
+    /// Check the defaults of the params against their declared types, like settings.
+    pub fn check_defaults(&self) -> Vec<SettingProblem> {
+        self.params
+            .iter()
+            .filter_map(|(key, param)| param.check(key, param.default.as_ref()?))
+            .collect()
+    }
+
+    /// Placeholder values for the required settings (see [`ParamKind::placeholder`]).
+    ///
+    /// Required settings without a placeholder are left out.
+    pub fn placeholder_settings(&self) -> toml::Table {
+        self.params
+            .iter()
+            .filter(|(_, param)| param.required)
+            .filter_map(|(key, param)| Some((key.clone(), param.kind.placeholder()?)))
+            .collect()
+    }
+
+    /// Merge the params from a parent theme.
+    ///
+    /// Params declared by both keep the child's declaration, but take the parent's default if the
+    /// child has none.
+    pub fn inherit(&mut self, parent: &ThemeManifest) {
+        for (key, parent_param) in &parent.params {
+            match self.params.get_mut(key) {
+                Some(param) => {
+                    if param.default.is_none() && !param.required {
+                        param.default = parent_param.default.clone();
+                    }
+                }
+                None => {
+                    self.params.insert(key.clone(), parent_param.clone());
+                }
+            }
+        }
+    }
 }
 
 impl Theme {
+    /// A theme without reading its manifest. See [`Theme::load`].
     pub fn new(basedir: &BaseDir, name: &str) -> Self {
         Self {
             basedir: basedir.clone(),
             themefile: basedir.join(&format!("themes/{name}/theme.typ")),
             name: name.to_string(),
+            manifest: None,
+            parent: None,
         }
     }
 
+    /// A theme with its `theme.toml` manifest if it has one, and its parents.
+    pub fn load(basedir: &BaseDir, name: &str) -> Result<Self, Error> {
+        Self::load_chain(basedir, name, &mut Vec::new())
+    }
+
+    /// Load a theme, where `children` are the themes inheriting from it (to detect cycles).
+    fn load_chain(
+        basedir: &BaseDir,
+        name: &str,
+        children: &mut Vec<String>,
+    ) -> Result<Self, Error> {
+        let mut theme = Self::new(basedir, name);
+
+        let manifest_path = theme.dir().absolute().join("theme.toml");
+        if manifest_path.is_file() {
+            let manifest = std::fs::read_to_string(&manifest_path).context(ReadFileSnafu {
+                path: manifest_path.clone(),
+            })?;
+            let manifest: ThemeManifest =
+                toml::from_str(&manifest).context(ThemeManifestSnafu {
+                    path: manifest_path.clone(),
+                })?;
+            if manifest.name != name {
+                warn!(
+                    "Theme {name} is named {} in its manifest {manifest_path}",
+                    manifest.name
+                );
+            }
+            theme.manifest = Some(manifest);
+        } else {
+            debug!("Theme {name} has no manifest, its settings are not checked");
+        }
+
+        let parent_name = theme.manifest.as_ref().and_then(|m| m.parent.clone());
+        if let Some(parent_name) = parent_name {
+            children.push(name.to_string());
+            ensure!(
+                !children.contains(&parent_name),
+                ThemeCycleSnafu {
+                    chain: format!("{} -> {parent_name}", children.join(" -> ")),
+                }
+            );
+            ensure!(
+                basedir
+                    .join(format!("themes/{parent_name}"))
+                    .absolute()
+                    .is_dir(),
+                NoParentThemeSnafu {
+                    theme: name.to_string(),
+                    parent: parent_name.clone(),
+                }
+            );
+
+            let parent = Self::load_chain(basedir, &parent_name, children)?;
+
+            if let (Some(manifest), Some(parent_manifest)) = (&mut theme.manifest, &parent.manifest)
+            {
+                manifest.inherit(parent_manifest);
+            }
+
+            theme.parent = Some(Box::new(parent));
+        }
+
+        // Defaults are checked once inherited, since a child may declare another type
+        if let Some(manifest) = &theme.manifest {
+            let problems = manifest.check_defaults();
+            ensure!(
+                problems.is_empty(),
+                ThemeDefaultsSnafu {
+                    path: manifest_path,
+                    problems,
+                }
+            );
+        }
+
+        Ok(theme)
+    }
+
+    /// Problems which prevent the theme from being used, if any.
+    ///
+    /// A theme needs a `theme.typ` (its own or from a parent), which exports a `zine` function.
+    /// This is checked by compiling a document which only imports it. Errors inside the theme,
+    /// like a syntax error or a missing import, are reported where they are.
+    pub fn problems(&self) -> Vec<String> {
+        if self.resolve("theme.typ").is_none() {
+            return vec![format!("missing {}", self.themefile.path)];
+        }
+
+        let check = format!(
+            "#import {}: zine\n#assert.eq(type(zine), function, message: \"zine is not a function\")\n",
+            typst_string(&format!("/{}", self.themefile.path))
+        );
+        let source = Source::new(
+            FileId::new_fake(VirtualPath::new(self.dir().path.join("check.typ"))),
+            check,
+        );
+
+        let world = match ZineWorld::new(&self.basedir, source, &self.fonts()) {
+            Ok(world) => world,
+            Err(e) => return vec![e.to_string()],
+        };
+        let errors = match typst::compile::<PagedDocument>(&world).output {
+            Ok(_) => return Vec::new(),
+            Err(errors) => errors,
+        };
+        errors
+            .iter()
+            .zip(diagnostics(&world, None, &errors, &[]))
+            .map(|(error, diagnostic)| {
+                // Only the import of zine and the type check are in the check document
+                if error.span.id() == Some(world.main()) {
+                    format!(
+                        "{} does not export a zine function: {}",
+                        self.themefile.path, error.message
+                    )
+                } else {
+                    diagnostic.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// The theme folder, like `themes/<name>`.
+    pub fn dir(&self) -> RootPath {
+        self.basedir.join(format!("themes/{}", self.name))
+    }
+
+    /// This theme and its parents, from child to root.
+    pub fn chain(&self) -> Vec<&Theme> {
+        let mut chain = vec![self];
+        let mut theme = self;
+        while let Some(parent) = &theme.parent {
+            chain.push(parent);
+            theme = parent;
+        }
+        chain
+    }
+
+    /// Check frontmatter settings for this theme, if it has a manifest.
+    pub fn check_settings(
+        &self,
+        settings: &toml::Table,
+        origins: &HashMap<String, RootPath>,
+        zine: &ZineFile,
+    ) -> Result<(), Error> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+
+        let problems = manifest.check(settings);
+        ensure!(
+            problems.is_empty(),
+            ThemeSettingsSnafu {
+                path: zine.file.absolute(),
+                theme: self.name.clone(),
+                problems,
+                origins: origins.clone(),
+            }
+        );
+
+        Ok(())
+    }
+
+    /// The declared param for a setting, if the theme has a manifest.
+    pub fn param(&self, key: &str) -> Option<&ThemeParam> {
+        self.manifest.as_ref()?.params.get(key)
+    }
+
+    /// Absolute paths to the fonts bundled with the theme and its parents.
+    pub fn fonts(&self) -> Vec<Utf8PathBuf> {
+        self.chain()
+            .into_iter()
+            .filter_map(|theme| Some((theme, theme.manifest.as_ref()?)))
+            .flat_map(|(theme, manifest)| {
+                manifest
+                    .fonts
+                    .iter()
+                    .map(|font| theme.dir().absolute().join(font))
+            })
+            .collect()
+    }
+
     pub fn relative_file(&self) -> RootPath {
         self.themefile.clone()
     }
@@ -29,8 +463,21 @@ impl Theme {
         self.themefile.relative_to_zine(zine)
     }
 
+    /// A file of the theme, relative to its folder, from this theme or else the first parent
+    /// which has it.
+    pub fn resolve(&self, path: impl AsRef<Utf8Path>) -> Option<RootPath> {
+        self.chain()
+            .into_iter()
+            .map(|theme| self.basedir.join(theme.dir().path.join(path.as_ref())))
+            .find(|file| file.absolute().exists())
+    }
+
+    /// A theme resource, from this theme or else the first parent which has it.
     pub fn theme_resource_relative_from_basedir(&self, path: &str) -> Utf8PathBuf {
-        let res = Utf8PathBuf::from(&format!("themes/{}/{}", &self.name, path));
+        let res = self
+            .resolve(path)
+            .map(|file| file.path)
+            .unwrap_or_else(|| self.dir().path.join(path));
         debug!(
             "theme_resource_relative_from_basedir(basedir: {}, path: {})\n  -> {}",
             self.basedir, path, res
@@ -39,7 +486,11 @@ impl Theme {
     }
 
     pub fn zine_resource_relative_from_theme(&self, path: &str, zine: &ZineFile) -> Utf8PathBuf {
-        let res = Utf8PathBuf::from("../..").join(zine.zine_resource_relative_from_basedir(path));
+        let res = zine
+            .file
+            .root
+            .join(zine.zine_resource_relative_from_basedir(path))
+            .relative_to_theme(self);
         debug!(
             "Resource {} for zine {} is {} from theme",
             path,
@@ -49,3 +500,136 @@ impl Theme {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::temp_basedir;
+
+    fn manifest() -> ThemeManifest {
+        toml::from_str(
+            r##"
+            name = "footheme"
+            version = "1.0.0"
+
+            [params.cover_color]
+            type = "color"
+            default = "#ff0000"
+
+            [params.logo]
+            type = "path"
+            required = true
+            "##,
+        )
+        .unwrap()
+    }
+
+    fn settings(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn valid_settings() {
+        assert_eq!(manifest().check(&settings("logo = \"logo.png\"")), vec![]);
+        assert_eq!(
+            manifest().check(&settings("logo = \"logo.png\"\ncover_color = \"#000\"")),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn inherited_params() {
+        let mut child: ThemeManifest = toml::from_str(
+            r##"
+            name = "bartheme"
+            parent = "footheme"
+
+            [params.cover_color]
+            type = "color"
+
+            [params.columns]
+            type = "int"
+            default = 2
+            "##,
+        )
+        .unwrap();
+        child.inherit(&manifest());
+
+        assert_eq!(
+            child.params.keys().collect::<Vec<_>>(),
+            vec!["columns", "cover_color", "logo"]
+        );
+        assert_eq!(
+            child.params["cover_color"].default,
+            Some(toml::Value::String("#ff0000".to_string()))
+        );
+        assert!(child.params["logo"].required);
+    }
+
+    #[test]
+    fn inherited_files() {
+        let (_dir, basedir) = temp_basedir(&[
+            (
+                "themes/parent/theme.typ",
+                "#import \"cover.typ\": cover\n#import \"back.typ\": back\n#let zine = cover + \", \" + back\n",
+            ),
+            ("themes/parent/cover.typ", "#let cover = \"parent cover\"\n"),
+            ("themes/parent/back.typ", "#let back = \"parent back\"\n"),
+            ("themes/child/theme.toml", "name = \"child\"\nparent = \"parent\"\n"),
+            ("themes/child/cover.typ", "#let cover = \"child cover\"\n"),
+        ]);
+
+        let theme = Theme::load(&basedir, "child").unwrap();
+        assert_eq!(theme.themefile.path, "themes/child/theme.typ");
+        assert_eq!(
+            theme.resolve("cover.typ").unwrap().path,
+            "themes/child/cover.typ"
+        );
+        assert_eq!(
+            theme.resolve("back.typ").unwrap().path,
+            "themes/parent/back.typ"
+        );
+        assert!(theme.resolve("missing.typ").is_none());
+
+        // The parent's theme.typ imports the child's cover, and falls back to its own back
+        let source = Source::new(
+            FileId::new_fake(VirtualPath::new("content/check.typ")),
+            "#import \"/themes/child/theme.typ\": zine\n#assert.eq(zine, \"child cover, parent back\")\n"
+                .to_string(),
+        );
+        let world = ZineWorld::new(&basedir, source, &[]).unwrap();
+        if let Err(errors) = typst::compile::<PagedDocument>(&world).output {
+            panic!("{errors:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert_eq!(
+            manifest().check(&settings("cover_colour = \"#000\"\ncover_color = \"red\"")),
+            vec![
+                SettingProblem::WrongType {
+                    key: "cover_color".to_string(),
+                    expected: ParamKind::Color
+                },
+                SettingProblem::Unknown("cover_colour".to_string()),
+                SettingProblem::Missing("logo".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_defaults() {
+        assert_eq!(manifest().check_defaults(), Vec::new());
+
+        let mut manifest = manifest();
+        manifest.params.get_mut("cover_color").unwrap().default = Some("red".into());
+        assert_eq!(
+            manifest.check_defaults(),
+            vec![SettingProblem::WrongType {
+                key: "cover_color".to_string(),
+                expected: ParamKind::Color
+            }]
+        );
+    }
+}
@@ -1,13 +1,415 @@
+use camino::Utf8PathBuf;
+
+use crate::impose::Duplex;
+
 #[derive(Copy, Clone, Debug, clap::ValueEnum)]
 pub enum CompileMode {
     Png,
     Pdf,
+    /// PDF imposed two-up for saddle-stitch binding
+    Booklet,
+}
+
+/// How Typst errors and warnings are reported.
+#[derive(Copy, Clone, Debug, Default, clap::ValueEnum)]
+pub enum DiagnosticsFormat {
+    /// Logged with the location in the markdown when possible
+    #[default]
+    Human,
+    /// Printed to stdout, as one JSON object per line
+    Json,
 }
 
-/// Sanitize markdown content for Tyspt consumption
+/// Options for compiling a zine, shared by the CLI and the watcher.
+#[derive(Clone, Debug, clap::Args)]
+pub struct CompileOptions {
+    #[clap(short, long, default_value = "pdf")]
+    pub mode: CompileMode,
+    /// How the printer flips sheets in booklet mode
+    #[clap(long, default_value = "short-edge")]
+    pub duplex: Duplex,
+    /// Also write the Typst generated from Markdown next to the source, for debugging
+    #[clap(long)]
+    pub write_typ: bool,
+    /// Only compile Markdown zines with these themes (all themes from the frontmatter by default)
+    #[clap(short, long = "theme")]
+    pub themes: Vec<String>,
+    /// Write outputs in this folder (relative to the basedir, e.g. `public`) instead of next to
+    /// the sources, mirroring the structure of the content folder
+    #[clap(short, long)]
+    pub out_dir: Option<Utf8PathBuf>,
+    /// How errors and warnings are reported
+    #[clap(long, default_value = "human")]
+    pub diagnostics: DiagnosticsFormat,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            mode: CompileMode::Pdf,
+            duplex: Duplex::default(),
+            write_typ: false,
+            themes: Vec::new(),
+            out_dir: None,
+            diagnostics: DiagnosticsFormat::default(),
+        }
+    }
+}
+
+/// Escape text so that it's displayed as is in Typst markup.
+///
+/// Characters which are always markup (like `#`, `*` or `[`) are escaped with a backslash.
+/// Other characters only have a meaning in some context, and are escaped only there:
+///
+/// - `=`, `-`, `+`, `/` and `1.` at the start of a line (headings and lists)
+/// - `//` and `/*` (comments)
+/// - `--`, `-?`, `-1` and `...` (shorthands)
+/// - `(` and `.` at the very start, because the text may follow a function call like `#emph[]`
 ///
-/// - replace `@` with `\@`
-/// - TODO
+/// The text may be inserted at the start of a line, so its start is considered a line start.
+/// Quotes are not escaped, so that they become typographic quotes.
 pub fn typst_escape(s: &str) -> String {
-    s.replace("@", "\\@")
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len());
+
+    // Only whitespace since the start of the line
+    let mut line_start = true;
+    // Only digits since the start of the line
+    let mut line_digits = false;
+
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).copied();
+
+        let escape = match c {
+            '\\' | '#' | '$' | '*' | '_' | '`' | '<' | '@' | '[' | ']' | '~' => true,
+            '=' | '+' | '/' if line_start => true,
+            '/' => matches!(next, Some('/' | '*')),
+            '-' => {
+                line_start
+                    || matches!(next, Some('-' | '?'))
+                    || next.is_some_and(|next| next.is_numeric())
+            }
+            '.' => i == 0 || line_digits || (next == Some('.') && chars.get(i + 2) == Some(&'.')),
+            '(' => i == 0,
+            _ => false,
+        };
+
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+
+        if is_newline(c) {
+            line_start = true;
+            line_digits = false;
+        } else if c.is_ascii_digit() && (line_start || line_digits) {
+            line_start = false;
+            line_digits = true;
+        } else if !c.is_whitespace() {
+            line_start = false;
+            line_digits = false;
+        }
+    }
+
+    out
+}
+
+/// Line breaks, as understood by Typst.
+fn is_newline(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\x0B' | '\x0C' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Quote a string as a Typst string literal, for example for raw text.
+pub fn typst_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Whether a value is a Typst length or ratio with a unit, like `12pt` or `50%`.
+pub fn is_typst_length(value: &str) -> bool {
+    ["%", "pt", "mm", "cm", "in", "em"].iter().any(|unit| {
+        value
+            .strip_suffix(unit)
+            .is_some_and(|number| number.parse::<f64>().is_ok())
+    })
+}
+
+/// Whether a value is a hex color code, like `#f00` or `#ff0000aa`.
+pub fn is_hex_color(value: &str) -> bool {
+    value.strip_prefix('#').is_some_and(|hex| {
+        [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// A value passed from zinifier to Typst code, for example as argument to the theme.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypstValue {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// A string literal
+    Str(String),
+    /// Content from text, which is escaped as markup
+    Content(String),
+    /// A length or ratio, like `12pt` or `50%`
+    Length(String),
+    /// A color from its hex code, like `#ff0000`
+    Color(String),
+    Array(Vec<TypstValue>),
+    Dict(Vec<(String, TypstValue)>),
+}
+
+impl TypstValue {
+    /// A length, if the value is valid (see [`is_typst_length`]).
+    pub fn length(value: &str) -> Option<Self> {
+        is_typst_length(value).then(|| Self::Length(value.to_string()))
+    }
+
+    /// A color, if the value is a valid hex code (see [`is_hex_color`]).
+    pub fn color(value: &str) -> Option<Self> {
+        is_hex_color(value).then(|| Self::Color(value.to_string()))
+    }
+
+    /// Serialize the value as Typst code.
+    pub fn to_typst(&self) -> String {
+        match self {
+            Self::None => "none".to_string(),
+            Self::Bool(b) => b.to_string(),
+            Self::Int(i) => i.to_string(),
+            // Debug formatting always keeps the decimal point, so it stays a float in Typst
+            Self::Float(f) if f.is_finite() => format!("{f:?}"),
+            Self::Float(f) if f.is_nan() => "float.nan".to_string(),
+            Self::Float(f) if *f > 0.0 => "float.inf".to_string(),
+            Self::Float(_) => "-float.inf".to_string(),
+            Self::Str(s) => typst_string(s),
+            Self::Content(s) => format!("[{}]", typst_escape(s)),
+            Self::Length(l) => l.to_string(),
+            Self::Color(c) => format!("rgb({})", typst_string(c)),
+            Self::Array(values) if values.is_empty() => "()".to_string(),
+            Self::Array(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_typst()).collect();
+                // A trailing comma makes sure a single value is still an array
+                format!("({},)", values.join(", "))
+            }
+            Self::Dict(entries) if entries.is_empty() => "(:)".to_string(),
+            Self::Dict(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", typst_string(k), v.to_typst()))
+                    .collect();
+                format!("({})", entries.join(", "))
+            }
+        }
+    }
+}
+
+impl From<&toml::Value> for TypstValue {
+    /// Convert a native TOML value.
+    ///
+    /// Strings which are lengths (like `12pt`) or hex colors (like `#ff0000`) become typed values,
+    /// and dates are passed as strings. Declaring a `string` param in the theme manifest keeps
+    /// them as strings.
+    fn from(value: &toml::Value) -> Self {
+        match value {
+            toml::Value::Boolean(b) => Self::Bool(*b),
+            toml::Value::Integer(i) => Self::Int(*i),
+            toml::Value::Float(f) => Self::Float(*f),
+            toml::Value::String(s) => Self::length(s)
+                .or_else(|| Self::color(s))
+                .unwrap_or_else(|| Self::Str(s.clone())),
+            toml::Value::Datetime(d) => Self::Str(d.to_string()),
+            toml::Value::Array(values) => Self::Array(values.iter().map(Self::from).collect()),
+            toml::Value::Table(table) => Self::Dict(
+                table
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Self::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use typst::syntax::{parse, SyntaxKind, SyntaxNode};
+
+    /// Text displayed by Typst markup, if it only contains plain text.
+    fn displayed_text(node: &SyntaxNode, out: &mut String) {
+        match node.kind() {
+            SyntaxKind::Markup => {
+                for child in node.children() {
+                    displayed_text(child, out);
+                }
+            }
+            SyntaxKind::Text
+            | SyntaxKind::Space
+            | SyntaxKind::Parbreak
+            | SyntaxKind::SmartQuote => out.push_str(node.text()),
+            SyntaxKind::Escape => out.push_str(&node.text()[1..]),
+            kind => panic!("Unexpected {kind:?} in Typst markup"),
+        }
+    }
+
+    fn assert_roundtrip(text: &str) {
+        let escaped = typst_escape(text);
+        let markup = parse(&escaped);
+        assert!(
+            !markup.erroneous(),
+            "Invalid markup for {text:?}: {escaped:?}"
+        );
+
+        let mut displayed = String::new();
+        displayed_text(&markup, &mut displayed);
+        assert_eq!(displayed, text, "Escaped as {escaped:?}");
+    }
+
+    #[test]
+    fn escape_markup() {
+        assert_eq!(typst_escape("#strong[a]"), "\\#strong\\[a\\]");
+        assert_eq!(typst_escape("me@example.org"), "me\\@example.org");
+        assert_eq!(typst_escape("$5 *each*"), "\\$5 \\*each\\*");
+    }
+
+    #[test]
+    fn escape_line_start() {
+        assert_eq!(typst_escape("= Title"), "\\= Title");
+        assert_eq!(typst_escape("a = b"), "a = b");
+        assert_eq!(typst_escape("a\n  - b - c"), "a\n  \\- b - c");
+        assert_eq!(typst_escape("12. b"), "12\\. b");
+        assert_eq!(typst_escape("a 12. b"), "a 12. b");
+    }
+
+    #[test]
+    fn escape_shorthands() {
+        assert_eq!(typst_escape("a -- b"), "a \\-- b");
+        assert_eq!(typst_escape("-1"), "\\-1");
+        assert_eq!(typst_escape("well..."), "well\\...");
+        assert_eq!(typst_escape("https://a"), "https:\\//a");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            TypstValue::Str("Say \"hi\" \\o/".to_string()).to_typst(),
+            "\"Say \\\"hi\\\" \\\\o/\""
+        );
+        assert_eq!(
+            TypstValue::Content("#1 *fan*".to_string()).to_typst(),
+            "[\\#1 \\*fan\\*]"
+        );
+        assert_eq!(TypstValue::Float(2.0).to_typst(), "2.0");
+        assert_eq!(TypstValue::length("12pt").unwrap().to_typst(), "12pt");
+        assert_eq!(TypstValue::length("12"), None);
+        assert_eq!(
+            TypstValue::color("#ff0000").unwrap().to_typst(),
+            "rgb(\"#ff0000\")"
+        );
+        assert_eq!(TypstValue::color("red"), None);
+        assert_eq!(TypstValue::Array(vec![]).to_typst(), "()");
+        assert_eq!(
+            TypstValue::Array(vec![TypstValue::Int(1)]).to_typst(),
+            "(1,)"
+        );
+        assert_eq!(TypstValue::Dict(vec![]).to_typst(), "(:)");
+        assert_eq!(
+            TypstValue::Dict(vec![("a b".to_string(), TypstValue::Bool(true))]).to_typst(),
+            "(\"a b\": true)"
+        );
+    }
+
+    #[test]
+    fn toml_values() {
+        let table: toml::Table = toml::from_str(
+            r##"
+            debug = true
+            columns = 2
+            ratio = 1.5
+            title_size = "24pt"
+            accent = "#ff0000"
+            font = "Libertinus Serif"
+            margins = ["1cm", "2cm"]
+            cover = { color = "#000", opacity = 50 }
+            "##,
+        )
+        .unwrap();
+        let typst: Vec<String> = table
+            .iter()
+            .map(|(k, v)| format!("{k}: {}", TypstValue::from(v).to_typst()))
+            .collect();
+
+        assert_eq!(
+            typst,
+            vec![
+                "accent: rgb(\"#ff0000\")",
+                "columns: 2",
+                "cover: (\"color\": rgb(\"#000\"), \"opacity\": 50)",
+                "debug: true",
+                "font: \"Libertinus Serif\"",
+                "margins: (1cm, 2cm,)",
+                "ratio: 1.5",
+                "title_size: 24pt",
+            ]
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        for text in [
+            "",
+            "Plain text, with \"quotes\" and 'apostrophes'.",
+            "= Not a heading\n+ not a list\n/ not: a term list\n1. not an enum",
+            "// not a comment /* nor this */",
+            "(after a call) [brackets] <label> @ref $math$ `raw` \\ ~",
+            "a---b -? -1 .... ===",
+        ] {
+            assert_roundtrip(text);
+        }
+    }
+
+    /// Property-style test: any text made of Typst syntax characters is displayed as is.
+    #[test]
+    fn roundtrip_random() {
+        const ALPHABET: &[char] = &[
+            'a', 'Z', '1', '9', ' ', ' ', '\n', '\r', '\t', 'é', '\\', '#', '$', '*', '_', '`',
+            '<', '>', '@', '[', ']', '(', ')', '{', '}', '~', '=', '+', '-', '/', '.', ',', ':',
+            ';', '?', '\'', '"', '%', '&', '^', '|',
+        ];
+
+        // Small xorshift generator, deterministic so failures can be reproduced
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..5000 {
+            let len = random() % 24;
+            let text: String = (0..len)
+                .map(|_| ALPHABET[(random() % ALPHABET.len() as u64) as usize])
+                .collect();
+            assert_roundtrip(&text);
+        }
+    }
 }
@@ -1,15 +1,28 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use glob::glob;
 use tokio::runtime::Builder as RuntimeBuilder;
-use watchexec::Watchexec;
+use watchexec::{WatchedPath, Watchexec};
 use watchexec_signals::Signal;
 
-use std::time::Duration;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use crate::{cli::SourceType, path::RootPath, typ::CompileMode};
+use crate::{
+    cli::SourceType,
+    frontmatter::{defaults_files, split_frontmatter},
+    path::RootPath,
+    theme::Theme,
+    typ::CompileOptions,
+};
 
 fn to_be_watched(
-    root: &Utf8Path,
+    theme_dirs: &[Utf8PathBuf],
     file: &Utf8Path,
     root_filter: impl Fn(&Utf8Path) -> Option<Utf8PathBuf>,
     parent_filter: impl Fn(&Utf8Path) -> Option<Utf8PathBuf>,
@@ -24,74 +37,175 @@ fn to_be_watched(
         })
         .collect();
 
-    // Watch files (not folders) in the 'root' directory
-    info!("ROOT {root}");
-    let watched2: Vec<Utf8PathBuf> = glob(&format!("{}/themes/**/*", root))
-        .unwrap()
-        .filter_map(|x| {
-            // let x = x.unwrap().into_path();
-            let x = x.unwrap();
-            let x = Utf8PathBuf::from_path_buf(x).unwrap();
-            return root_filter(&x);
-        })
-        .collect();
+    // Watch files (not folders) in the theme directories
+    for theme_dir in theme_dirs {
+        info!("THEME {theme_dir}");
+        let watched2: Vec<Utf8PathBuf> = glob(&format!("{}/**/*", theme_dir))
+            .unwrap()
+            .filter_map(|x| {
+                // let x = x.unwrap().into_path();
+                let x = x.unwrap();
+                let x = Utf8PathBuf::from_path_buf(x).unwrap();
+                return root_filter(&x);
+            })
+            .collect();
 
-    watched.extend(watched2);
+        watched.extend(watched2);
+    }
 
     watched
 }
 
-pub fn watch(sourcetype: &SourceType, path: &RootPath) {
+pub fn watch(sourcetype: &SourceType, path: &RootPath, options: &CompileOptions) {
     // We watch a specific file, but in the context of an entire basedir...
     let root = path.root.to_path_buf();
     let file = path.clone();
 
-    let root2 = root.to_path_buf();
-    let file2 = file.clone();
-    let sourcetype2 = sourcetype.clone();
+    // Defaults in parent folders change the frontmatter of a markdown zine too, and may be
+    // created later
+    let defaults: Vec<Utf8PathBuf> = match sourcetype {
+        SourceType::Markdown => defaults_files(path),
+        SourceType::Typst => Vec::new(),
+    };
 
     // First compile a first time
-    let _ = sourcetype.compile(&file2, CompileMode::Pdf);
-
-    let parent_filter = match sourcetype {
-        SourceType::Markdown => is_not_pdf_or_typ,
-        SourceType::Typst => is_not_pdf,
-    };
+    let _ = sourcetype.compile(&file, options);
 
     let rt = RuntimeBuilder::new_current_thread()
         .enable_time()
         .enable_io()
         .build()
         .unwrap();
-    rt.block_on(async {
-        info!("Watching {root2}");
-        let wx = Watchexec::new(move |mut action| {
-            // if Ctrl-C is received, quit
-            if action.signals().any(|sig| sig == Signal::Interrupt) {
-                action.quit();
-                return action;
-                // exit(0);
-            }
 
-            for event in action.events.iter() {
-                trace!("WATCHEXEC EVENT: {event:?}");
-            }
+    // The themes of a markdown zine may change, then the watch starts over with their folders
+    loop {
+        let theme_dirs = theme_dirs(sourcetype, path);
+        // The frontmatter, the defaults and the manifests of the themes choose the theme folders
+        let reloading: Vec<Utf8PathBuf> = match sourcetype {
+            SourceType::Markdown => defaults
+                .iter()
+                .cloned()
+                .chain([file.absolute()])
+                .chain(theme_dirs.iter().map(|dir| dir.join("theme.toml")))
+                .collect(),
+            SourceType::Typst => Vec::new(),
+        };
+        // Outputs are written next to the zine or in the output directory, and must not trigger a
+        // new compilation
+        let sources = typst_sources(sourcetype, path);
+        let out_dir = options.out_dir.as_deref();
+        let parent_filter = |path: &Utf8Path| {
+            let name = path.file_name().unwrap();
+            (!name.starts_with(".") && !is_output(path, sourcetype, &sources, out_dir))
+                .then(|| path.to_path_buf())
+        };
+        let watched = to_be_watched(&theme_dirs, &file.absolute(), is_not_pdf, parent_filter);
+        let reload = Arc::new(AtomicBool::new(false));
 
-            if let Err(e) = sourcetype2.compile(&file2, CompileMode::Pdf) {
-                error!("{}", e);
-            }
+        let file2 = file.clone();
+        let sourcetype2 = sourcetype.clone();
+        let options2 = options.clone();
+        let defaults2 = defaults.clone();
+        let watched2 = watched.clone();
+        let reloading2 = reloading.clone();
+        let reload2 = reload.clone();
+        rt.block_on(async {
+            info!("Watching {root}");
+            let wx = Watchexec::new(move |mut action| {
+                // if Ctrl-C is received, quit
+                if action.signals().any(|sig| sig == Signal::Interrupt) {
+                    action.quit();
+                    return action;
+                    // exit(0);
+                }
 
-            action
-        })
-        .unwrap();
-        wx.config.pathset(
-            to_be_watched(&root, &file.absolute(), is_not_pdf, parent_filter)
-                .into_iter()
-                .map(|x| x.as_std_path().to_path_buf()),
+                for event in action.events.iter() {
+                    trace!("WATCHEXEC EVENT: {event:?}");
+                }
+
+                // The folders of the defaults are watched for new defaults, not for their other
+                // files
+                let is_defaults = |path: &Path| defaults2.iter().any(|d| d.as_std_path() == path);
+                let paths: Vec<&Path> = action.paths().map(|(path, _)| path).collect();
+                if !paths.is_empty()
+                    && !paths.iter().any(|path| {
+                        is_defaults(path) || watched2.iter().any(|w| w.as_std_path() == *path)
+                    })
+                {
+                    return action;
+                }
+
+                if paths
+                    .iter()
+                    .any(|path| reloading2.iter().any(|r| r.as_std_path() == *path))
+                {
+                    reload2.store(true, Ordering::SeqCst);
+                    action.quit();
+                }
+
+                if let Err(e) = sourcetype2.compile(&file2, &options2) {
+                    error!("{}", e);
+                }
+
+                action
+            })
+            .unwrap();
+            wx.config.pathset(
+                watched
+                    .iter()
+                    .map(|x| WatchedPath::from(x.as_std_path().to_path_buf()))
+                    .chain(defaults.iter().filter_map(|defaults| {
+                        Some(WatchedPath::non_recursive(
+                            defaults.parent()?.as_std_path().to_path_buf(),
+                        ))
+                    })),
+            );
+            wx.config.throttle(Duration::from_millis(100));
+            wx.main().await.unwrap().unwrap();
+        });
+
+        if !reload.load(Ordering::SeqCst) {
+            break;
+        }
+        info!(
+            "Themes may have changed, watching the themes of {} again",
+            file.path
         );
-        wx.config.throttle(Duration::from_millis(100));
-        wx.main().await.unwrap().unwrap();
-    });
+    }
+}
+
+/// The theme directories a zine depends on.
+///
+/// For a markdown zine, these are its themes and their parents. A typst zine may import anything,
+/// so all themes are watched.
+fn theme_dirs(sourcetype: &SourceType, path: &RootPath) -> Vec<Utf8PathBuf> {
+    let all_themes = vec![path.root.join("themes").absolute()];
+
+    let SourceType::Markdown = sourcetype else {
+        return all_themes;
+    };
+
+    let frontmatter = match split_frontmatter(path) {
+        Ok((frontmatter, _markdown)) => frontmatter,
+        Err(e) => {
+            warn!("{e}");
+            return all_themes;
+        }
+    };
+    let mut dirs = Vec::new();
+    for name in frontmatter.themes.keys() {
+        match Theme::load(&path.root, name) {
+            Ok(theme) => dirs.extend(theme.chain().iter().map(|theme| theme.dir().absolute())),
+            Err(e) => {
+                warn!("{e}");
+                return all_themes;
+            }
+        }
+    }
+
+    dirs.sort();
+    dirs.dedup();
+    dirs
 }
 
 fn is_not_pdf(path: &Utf8Path) -> Option<Utf8PathBuf> {
@@ -103,11 +217,53 @@ fn is_not_pdf(path: &Utf8Path) -> Option<Utf8PathBuf> {
     }
 }
 
-fn is_not_pdf_or_typ(path: &Utf8Path) -> Option<Utf8PathBuf> {
-    let name = path.file_name().unwrap();
-    if !name.starts_with(".") && !name.ends_with("pdf") && !name.ends_with("typ") {
-        Some(path.to_path_buf())
-    } else {
-        None
+/// The Typst files a zine is compiled from, which its outputs are named after.
+///
+/// A markdown zine is compiled through a `<name>.<theme>.typ` for each of its themes.
+fn typst_sources(sourcetype: &SourceType, path: &RootPath) -> Vec<RootPath> {
+    match sourcetype {
+        SourceType::Typst => vec![path.clone()],
+        // Frontmatter errors are already reported with the theme directories
+        SourceType::Markdown => split_frontmatter(path)
+            .map(|(frontmatter, _markdown)| {
+                frontmatter
+                    .themes
+                    .keys()
+                    .map(|theme| path.with_extension(&format!("{theme}.typ")))
+                    .collect()
+            })
+            .unwrap_or_default(),
     }
 }
+
+/// Whether a file is written by compiling the zine from its Typst `sources`.
+///
+/// These are `<source>.pdf`, `<source>.booklet.pdf` and `<source>.<page>.png` (in `out_dir` if
+/// any, see [`RootPath::output`]), and for a markdown zine the Typst sources themselves (see
+/// `--write-typ`).
+fn is_output(
+    path: &Utf8Path,
+    sourcetype: &SourceType,
+    sources: &[RootPath],
+    out_dir: Option<&Utf8Path>,
+) -> bool {
+    sources.iter().any(|source| {
+        if matches!(sourcetype, SourceType::Markdown) && path == source.absolute() {
+            return true;
+        }
+
+        let pdf = source.output(out_dir, "pdf");
+        if path == pdf || path == source.output(out_dir, "booklet.pdf") {
+            return true;
+        }
+
+        // One PNG per page
+        let stem = pdf.file_stem().unwrap_or_default();
+        path.parent() == pdf.parent()
+            && path
+                .file_name()
+                .and_then(|name| name.strip_prefix(stem)?.strip_prefix('.'))
+                .and_then(|name| name.strip_suffix(".png"))
+                .is_some_and(|page| !page.is_empty() && page.chars().all(|c| c.is_ascii_digit()))
+    })
+}
@@ -0,0 +1,186 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use typst::{
+    diag::FileResult,
+    foundations::{Bytes, Datetime},
+    syntax::{FileId, Source, VirtualPath},
+    text::{Font, FontBook},
+    utils::LazyHash,
+    Library, World,
+};
+use typst_cli::args::{DiagnosticFormat, FontArgs, Input, PackageArgs, ProcessArgs, WorldArgs};
+use typst_cli::world::SystemWorld;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{error::Error, path::BaseDir, theme::Theme};
+
+/// A Typst [`World`] where the main source lives in memory.
+///
+/// Every other file (themes, images, fonts, packages) is resolved from the [`BaseDir`] by the
+/// wrapped [`SystemWorld`], so the generated Typst for a Markdown zine never needs to be written
+/// to disk.
+///
+/// Files missing from a theme folder are taken from its parents, like `themes/child/cover.typ`
+/// from `themes/parent/cover.typ`. They keep the path in the child, so that their relative
+/// imports still look in the child first.
+pub struct ZineWorld {
+    inner: SystemWorld,
+    main: Source,
+    basedir: BaseDir,
+    /// Themes already loaded to resolve their files, `None` when they failed to load
+    themes: Mutex<HashMap<String, Option<Theme>>>,
+    /// Sources taken from a parent theme, under their path in the child theme
+    inherited: Mutex<HashMap<FileId, Source>>,
+    /// Files served from memory instead of the basedir
+    files: HashMap<FileId, Bytes>,
+}
+
+impl ZineWorld {
+    /// Fonts are searched in the system and in `fonts` (files or folders).
+    ///
+    /// Fails when the system world can't be set up, for example when the basedir doesn't exist.
+    pub fn new(basedir: &BaseDir, main: Source, fonts: &[Utf8PathBuf]) -> Result<Self, Error> {
+        // The main file is served from memory, so the system world never reads its input
+        let input = Input::Stdin;
+
+        let world_args = WorldArgs {
+            root: Some(basedir.as_std_path().to_path_buf()),
+            inputs: Vec::new(),
+            font: FontArgs {
+                font_paths: fonts
+                    .iter()
+                    .map(|font| font.as_std_path().to_path_buf())
+                    .collect(),
+                ignore_system_fonts: false,
+            },
+            package: PackageArgs {
+                package_path: None,
+                package_cache_path: None,
+            },
+            creation_timestamp: None,
+        };
+
+        let process_args = ProcessArgs {
+            jobs: None,
+            diagnostic_format: DiagnosticFormat::Human,
+            features: Vec::new(),
+        };
+
+        let inner = SystemWorld::new(&input, &world_args, &process_args).map_err(|e| {
+            let main_path = main.id().vpath().as_rootless_path().to_string_lossy();
+            Error::World {
+                path: basedir.join(main_path.as_ref()).absolute(),
+                message: e.to_string(),
+            }
+        })?;
+
+        Ok(Self {
+            inner,
+            main,
+            basedir: basedir.clone(),
+            themes: Mutex::default(),
+            inherited: Mutex::default(),
+            files: HashMap::new(),
+        })
+    }
+
+    /// Serve a file from memory, at a path relative to the basedir.
+    pub fn with_file(mut self, path: &Utf8Path, bytes: Bytes) -> Self {
+        let id = FileId::new(None, VirtualPath::new(path.as_std_path()));
+        self.files.insert(id, bytes);
+        self
+    }
+
+    /// The id of the file to read for `id`, which is in a parent theme when the file is missing
+    /// from the theme folder.
+    fn resolve(&self, id: FileId) -> FileId {
+        if id.package().is_some() {
+            return id;
+        }
+        let Some(path) = Utf8Path::from_path(id.vpath().as_rootless_path()) else {
+            return id;
+        };
+        let Ok(in_themes) = path.strip_prefix("themes") else {
+            return id;
+        };
+        let mut components = in_themes.components();
+        let Some(name) = components.next() else {
+            return id;
+        };
+        let file = components.as_path();
+        if file.as_str().is_empty() || self.basedir.join(path).absolute().exists() {
+            return id;
+        }
+
+        let mut themes = self.themes.lock().unwrap();
+        let theme = themes
+            .entry(name.as_str().to_string())
+            .or_insert_with(|| Theme::load(&self.basedir, name.as_str()).ok());
+        match theme.as_ref().and_then(|theme| theme.resolve(file)) {
+            Some(resolved) => {
+                trace!("Resolved {path} to {}", resolved.path);
+                FileId::new(None, VirtualPath::new(resolved.path.as_std_path()))
+            }
+            None => id,
+        }
+    }
+}
+
+impl World for ZineWorld {
+    fn library(&self) -> &LazyHash<Library> {
+        self.inner.library()
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        self.inner.book()
+    }
+
+    fn main(&self) -> FileId {
+        self.main.id()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            return Ok(self.main.clone());
+        }
+
+        let resolved = self.resolve(id);
+        if resolved == id {
+            self.inner.source(id)
+        } else {
+            // Diagnostics and relative imports use the path in the child theme
+            let source = self.inner.source(resolved)?;
+            let mut inherited = self.inherited.lock().unwrap();
+            match inherited.get_mut(&id) {
+                Some(cached) => {
+                    // Only reparse what changed, like the system world does
+                    if cached.text() != source.text() {
+                        cached.replace(source.text());
+                    }
+                    Ok(cached.clone())
+                }
+                None => {
+                    let cached = Source::new(id, source.text().to_string());
+                    inherited.insert(id, cached.clone());
+                    Ok(cached)
+                }
+            }
+        }
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        if let Some(bytes) = self.files.get(&id) {
+            return Ok(bytes.clone());
+        }
+        self.inner.file(self.resolve(id))
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.inner.font(index)
+    }
+
+    fn today(&self, offset: Option<i64>) -> Option<Datetime> {
+        self.inner.today(offset)
+    }
+}
@@ -1,4 +1,4 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 
 use crate::error::*;
 use crate::path::RootPath;
@@ -9,32 +9,70 @@ use snafu::prelude::*;
 use tiny_skia::Pixmap;
 use typst::{
     diag::Warned,
-    foundations::eco_format,
+    foundations::Bytes,
     syntax::{FileId, Source, VirtualPath},
 };
-use typst_cli::args::{DiagnosticFormat, FontArgs, Input, PackageArgs, ProcessArgs, WorldArgs};
-use typst_cli::compile::print_diagnostics;
-use typst_cli::world::SystemWorld;
 use typst_library::layout::PagedDocument;
 use typst_pdf::PdfOptions;
 
-use crate::{frontmatter::split_frontmatter, markdown_it::markdown_to_typst_content, theme::Theme};
+use crate::{
+    diag::{diagnostics, print_diagnostics, Diagnostic},
+    frontmatter::load_frontmatter,
+    impose::{saddle_stitch, Duplex},
+    markdown_it::markdown_to_typst_content_mapped,
+    srcmap::MarkdownSource,
+    theme::Theme,
+    typ::{CompileOptions, DiagnosticsFormat},
+    world::ZineWorld,
+};
+
+/// Pixels per point of the PNG renders.
+const PIXELS_PER_PT: f32 = 90.0;
 
 #[derive(Clone, Debug)]
 pub struct CompiledZine {
-    source: RootPath,
-    inner: PagedDocument,
+    pub(crate) source: RootPath,
+    pub(crate) inner: PagedDocument,
+    /// Warnings from Typst
+    pub warnings: Vec<Diagnostic>,
 }
 
 impl CompiledZine {
-    pub fn to_pdf(&self) -> Result<(), Error> {
+    /// Write the zine as PDF, in the output directory if any (see [`RootPath::output`]).
+    pub fn to_pdf(&self, out_dir: Option<&Utf8Path>) -> Result<(), Error> {
+        self.write_pdf(out_dir, "pdf")
+    }
+
+    /// Impose the zine for saddle-stitch binding, then write it to `<name>.booklet.pdf`.
+    pub fn to_booklet(&self, out_dir: Option<&Utf8Path>, duplex: Duplex) -> Result<(), Error> {
         let now = Instant::now();
 
-        let mut out = self.source.absolute();
-        out.set_extension("pdf");
+        let booklet = CompiledZine {
+            source: self.source.clone(),
+            inner: saddle_stitch(&self.inner, duplex),
+            warnings: self.warnings.clone(),
+        };
+        debug!("Imposition: {:.2?}", now.elapsed());
+
+        booklet.write_pdf(out_dir, "booklet.pdf")
+    }
 
-        // TODO: error
-        let pdf_bytes = typst_pdf::pdf(&self.inner, &PdfOptions::default()).unwrap();
+    fn write_pdf(&self, out_dir: Option<&Utf8Path>, extension: &str) -> Result<(), Error> {
+        let now = Instant::now();
+
+        let out = self.source.output(out_dir, extension);
+        create_parent_dir(&out)?;
+
+        let pdf_bytes = typst_pdf::pdf(&self.inner, &PdfOptions::default()).map_err(|errors| {
+            Error::PDFExport {
+                path: out.clone(),
+                message: errors
+                    .iter()
+                    .map(|error| error.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            }
+        })?;
         debug!("PDF export: {:.2?}", now.elapsed());
 
         let now = Instant::now();
@@ -53,7 +91,7 @@ impl CompiledZine {
             .inner
             .pages
             .par_iter()
-            .map(|p| (p.number, typst_render::render(&p, 90.0)))
+            .map(|p| (p.number, typst_render::render(&p, PIXELS_PER_PT)))
             .collect();
 
         debug!("PIXMAP export: {:.2?}s", now.elapsed());
@@ -61,14 +99,25 @@ impl CompiledZine {
         res
     }
 
-    pub fn to_png(&self) -> Result<(), Error> {
+    /// Render only the first page, if any.
+    pub fn first_pixmap(&self) -> Option<Pixmap> {
+        let page = self.inner.pages.first()?;
+        Some(typst_render::render(page, PIXELS_PER_PT))
+    }
+
+    /// Write every page of the zine as PNG, in the output directory if any.
+    pub fn to_png(&self, out_dir: Option<&Utf8Path>) -> Result<(), Error> {
         let now = Instant::now();
 
+        create_parent_dir(&self.source.output(out_dir, "png"))?;
+
         self.to_pixmap().par_iter().try_for_each(|(k, v)| {
-            let png = v.encode_png().unwrap();
-            let mut out = self.source.absolute();
-            out.set_extension(&format!("{k}.png"));
-            std::fs::write(&out, &png).context(PDFWriteSnafu {
+            let out = self.source.output(out_dir, &format!("{k}.png"));
+            let png = v.encode_png().map_err(|e| Error::PNGExport {
+                path: out.clone(),
+                message: e.to_string(),
+            })?;
+            std::fs::write(&out, &png).context(PNGWriteSnafu {
                 path: out.to_path_buf(),
             })?;
             Ok(())
@@ -79,109 +128,161 @@ impl CompiledZine {
     }
 }
 
+fn create_parent_dir(path: &Utf8Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context(OutputDirSnafu {
+            path: parent.to_path_buf(),
+        })?;
+    }
+
+    Ok(())
+}
+
 /// A zine on disk inside the [`BaseDir`].
 ///
 /// Create with [`TypstEnv::load`], then compile with [`ZineFile::compile`].
 #[derive(Clone, Debug)]
 pub struct ZineFile {
     pub file: RootPath,
-    // This is the main document in the typst environment, so let's say it's the zine we're compiling???
-    #[allow(dead_code)]
+    /// The main document in the typst environment, served from memory by [`ZineWorld`].
     pub source: Source,
+    /// Extra font files or folders, like the fonts bundled with a theme
+    pub fonts: Vec<Utf8PathBuf>,
+    /// The markdown zine this Typst was generated from, to report diagnostics against it
+    pub markdown: Option<MarkdownSource>,
+    /// Files served from memory instead of the disk, like the placeholder image of the gallery
+    pub files: Vec<(RootPath, Bytes)>,
 }
 
 impl ZineFile {
-    pub fn new(path: &RootPath) -> Self {
-        Self {
+    pub fn new(path: &RootPath) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(&path.absolute()).context(ReadFileSnafu {
+            path: path.absolute(),
+        })?;
+
+        Ok(Self {
             file: path.clone(),
-            // TODO: async/error
             source: Source::new(
                 FileId::new_fake(VirtualPath::new(path.path.as_std_path())),
-                std::fs::read_to_string(&path.absolute()).unwrap(),
+                text,
             ),
-        }
+            fonts: Vec::new(),
+            markdown: None,
+            files: Vec::new(),
+        })
     }
 
-    pub fn compile(&self) -> Result<CompiledZine, Error> {
+    /// Compile the zine, reporting Typst errors and warnings in the given format.
+    pub fn compile(&self, format: DiagnosticsFormat) -> Result<CompiledZine, Error> {
         let now = Instant::now();
 
-        let input = Input::Path(self.file.absolute().into());
-
-        let world_args = WorldArgs {
-            root: Some(self.file.root.as_std_path().to_path_buf()),
-            inputs: Vec::new(),
-            font: FontArgs {
-                font_paths: Vec::new(),
-                ignore_system_fonts: false,
-            },
-            package: PackageArgs {
-                package_path: None,
-                package_cache_path: None,
-            },
-            creation_timestamp: None,
-        };
+        let mut world = ZineWorld::new(&self.file.root, self.source.clone(), &self.fonts)?;
+        for (path, bytes) in &self.files {
+            world = world.with_file(&path.path, bytes.clone());
+        }
+        let Warned { output, warnings } = typst::compile::<PagedDocument>(&world);
 
-        let process_args = ProcessArgs {
-            jobs: None,
-            diagnostic_format: DiagnosticFormat::Human,
-            features: Vec::new(),
+        let (output, errors) = match output {
+            Ok(output) => (Some(output), Default::default()),
+            Err(errors) => (None, errors),
         };
+        let diagnostics = diagnostics(&world, self.markdown.as_ref(), &errors, &warnings);
+        print_diagnostics(&diagnostics, format);
 
-        let world = SystemWorld::new(&input, &world_args, &process_args).unwrap();
-        let Warned { output, warnings } = typst::compile::<PagedDocument>(&world);
-
-        let Ok(output) = output else {
-            print_diagnostics(
-                &world,
-                &output.unwrap_err(),
-                &warnings,
-                DiagnosticFormat::Human,
-            )
-            .map_err(|err| eco_format!("failed to print diagnostics ({err})"))
-            .unwrap();
+        let Some(output) = output else {
             error!("FAILED TO COMPILE ZINE.");
             return Err(Error::Typst {
                 path: self.file.absolute(),
+                diagnostics,
             });
         };
 
-        for w in &warnings {
-            warn!("{:?}", w);
-        }
-
         debug!("Compilation: {:.2?}s", now.elapsed());
 
         Ok(CompiledZine {
             source: self.file.clone(),
             inner: output,
+            warnings: diagnostics,
         })
     }
 
-    pub fn compile_md(&self) -> Result<CompiledZine, Error> {
-        let (frontmatter, markdown) = split_frontmatter(&self.file.absolute());
+    /// Compile a markdown zine once for every theme in its frontmatter.
+    ///
+    /// When [`CompileOptions::themes`] is not empty, only those themes are compiled.
+    pub fn compile_md(&self, options: &CompileOptions) -> Result<Vec<CompiledZine>, Error> {
+        let (frontmatter, markdown) = load_frontmatter(&self.file, self.source.text())?;
 
-        // Compile once for each theme
-        // for (theme_name, theme_settings) in &frontmatter.themes {
-        let (theme_name, _theme_settings) = frontmatter.themes.iter().next().unwrap();
-        let theme = Theme::new(&self.file.root, theme_name);
-
-        let mut out = String::new();
-        out.push_str(&frontmatter.with_typst_header(self, &theme));
-        out.push_str(&markdown_to_typst_content(&markdown));
-
-        let mut typst_file = self.file.absolute().to_path_buf();
-        typst_file.set_extension(&format!("{theme_name}.typ"));
-        std::fs::write(&typst_file, out).context(MDSaveSnafu {
-            path: typst_file.to_path_buf(),
-        })?;
+        for selected in &options.themes {
+            if !frontmatter.themes.contains_key(selected) {
+                warn!("Theme {selected} is not used by {}", self.file.absolute());
+            }
+        }
 
-        info!("Wrote to {typst_file}");
+        let mut theme_names: Vec<&String> = frontmatter
+            .themes
+            .keys()
+            .filter(|name| options.themes.is_empty() || options.themes.contains(*name))
+            .collect();
+        // Stable output order regardless of HashMap ordering
+        theme_names.sort();
+
+        ensure!(
+            !theme_names.is_empty(),
+            NoThemeSnafu {
+                path: self.file.absolute()
+            }
+        );
 
-        // Now generate new zine with typ file
-        let mut zine = self.clone();
-        zine.file = zine.file.root.join(&typst_file);
+        let (content, content_spans) =
+            markdown_to_typst_content_mapped(&markdown, frontmatter.print_links)?;
+        // The markdown is the end of the file, after the frontmatter
+        let markdown_offset = self.source.text().len() - markdown.len();
 
-        zine.compile()
+        // Compile once for each theme
+        theme_names
+            .par_iter()
+            .map(|theme_name| {
+                let theme = Theme::load(&self.file.root, theme_name)?;
+                if let Some(settings) = frontmatter.themes.get(*theme_name) {
+                    theme.check_settings(settings, &frontmatter.origins, self)?;
+                }
+
+                let (mut out, mut spans) = frontmatter.with_typst_header_mapped(self, &theme);
+                spans.append(content_spans.clone(), out.len(), markdown_offset);
+                out.push_str(&content);
+
+                // The generated typst source is never read back from disk, but it pretends to live
+                // next to the markdown so that relative paths to themes and images resolve
+                let typst_file = self.file.with_extension(&format!("{theme_name}.typ"));
+
+                if options.write_typ {
+                    std::fs::write(&typst_file.absolute(), &out).context(MDSaveSnafu {
+                        path: typst_file.absolute(),
+                    })?;
+
+                    info!("Wrote to {}", typst_file.absolute());
+                }
+
+                // Now generate new zine with the in-memory typst source
+                let zine = ZineFile {
+                    source: Source::new(
+                        FileId::new_fake(VirtualPath::new(typst_file.path.as_std_path())),
+                        out,
+                    ),
+                    file: typst_file,
+                    fonts: theme.fonts(),
+                    markdown: Some(MarkdownSource {
+                        path: self.file.path.clone(),
+                        text: self.source.text().to_string(),
+                        spans,
+                        origins: frontmatter.origins.clone(),
+                    }),
+                    files: self.files.clone(),
+                };
+
+                zine.compile(options.diagnostics)
+            })
+            .collect()
     }
 
     pub fn relative_dir(&self) -> RootPath {